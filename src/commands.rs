@@ -1,36 +1,91 @@
+use std::env;
+
 use async_trait::async_trait;
+use once_cell::sync::Lazy;
 use reqwest::Client as ReqwestClient;
 use twilight_http::{client::InteractionClient, Client as TwilightClient};
 use twilight_interactions::command::{CommandModel, CreateCommand};
 use twilight_model::{
     application::{
         command::Command,
-        interaction::{Interaction, InteractionData},
+        interaction::{Interaction, InteractionData, InteractionType},
     },
-    channel::Channel,
+    channel::{Channel, Message},
+    guild::Permissions,
     id::{
-        marker::{ApplicationMarker, InteractionMarker},
+        marker::{ApplicationMarker, GuildMarker, InteractionMarker, UserMarker},
         Id,
     },
 };
+use twilight_util::snowflake::Snowflake;
 
 use self::{
-    chat::ChatCommand, dream::DreamCommand, horde::HordeCommand, info::InfoCommand,
-    nano::NanoCommand, stats::StatsCommand,
+    chat::ChatCommand,
+    describe::DescribeCommand,
+    dream::DreamCommand,
+    horde::HordeCommand,
+    horde_chat::{HordeChatAutocomplete, HordeChatCommand},
+    info::InfoCommand,
+    keys::KeysCommand,
+    nano::NanoCommand,
+    safety::SafetyCommand,
+    stats::StatsCommand,
+    usage::UsageCommand,
 };
+use crate::storage::{unix_now, JobKind, JobOrigin, Storage};
 
 mod chat;
+mod describe;
 mod dream;
 mod horde;
+mod horde_chat;
 mod info;
+mod keys;
 mod nano;
+mod safety;
 mod stats;
+mod usage;
+
+/// Seconds an interaction token stays valid for follow-up edits.
+pub const INTERACTION_TOKEN_LIFETIME: u64 = 15 * 60;
+
+/// The user id in `BOT_OWNER_ID`. Commands that show state shared by every
+/// server are limited to this user.
+static BOT_OWNER_ID: Lazy<Option<u64>> = Lazy::new(|| {
+    env::var("BOT_OWNER_ID")
+        .ok()
+        .and_then(|id| id.trim().parse().ok())
+});
+
+/// Who invoked an interaction and when, read from its id, for the jobs it
+/// hands to a provider.
+fn job_origin(
+    user_id: Option<Id<UserMarker>>,
+    guild_id: Option<Id<GuildMarker>>,
+    interaction_id: Id<InteractionMarker>,
+) -> JobOrigin {
+    JobOrigin {
+        user_id: user_id.map(Id::get),
+        guild_id: guild_id.map(Id::get),
+        created_at: interaction_id.timestamp() as u64 / 1000,
+    }
+}
+
+/// Whether `user_id` is the bot owner. Nobody is when `BOT_OWNER_ID` is unset.
+fn is_bot_owner(user_id: Option<Id<UserMarker>>) -> bool {
+    user_id.is_some_and(|id| Some(id.get()) == *BOT_OWNER_ID)
+}
 
 pub struct CommandHandlerData<'a> {
     pub channel: Channel,
+    pub guild_id: Option<Id<GuildMarker>>,
+    pub user_id: Option<Id<UserMarker>>,
+    /// The invoking member's permissions in the channel, in guilds only.
+    pub member_permissions: Option<Permissions>,
     pub reqwest_client: ReqwestClient,
     pub interaction_client: InteractionClient<'a>,
     pub twilight_client: &'a TwilightClient,
+    pub storage: Storage,
 }
 
 #[async_trait]
@@ -46,6 +101,7 @@ pub trait CommandHandler {
 pub struct CommandDelegateData {
     pub reqwest_client: ReqwestClient,
     pub twilight_client: TwilightClient,
+    pub storage: Storage,
 }
 
 #[async_trait]
@@ -56,6 +112,8 @@ pub trait CommandDelegate {
         interaction: Interaction,
         application_id: Id<ApplicationMarker>,
     );
+    async fn resume_jobs(&self, application_id: Id<ApplicationMarker>);
+    async fn handle_message(&self, message: Message);
 }
 
 #[async_trait]
@@ -68,9 +126,16 @@ impl CommandDelegate for CommandDelegateData {
             ChatCommand::create_command(),
             NanoCommand::create_command(),
             StatsCommand::create_command(),
+            DescribeCommand::create_command(),
+            HordeChatCommand::create_command(),
+            SafetyCommand::create_command(),
+            KeysCommand::create_command(),
+            UsageCommand::create_command(),
         ]
         .map(std::convert::Into::into)
-        .to_vec()
+        .into_iter()
+        .chain([nano::edit_command()])
+        .collect()
     }
 
     async fn handle_interaction(
@@ -78,100 +143,287 @@ impl CommandDelegate for CommandDelegateData {
         interaction: Interaction,
         application_id: Id<ApplicationMarker>,
     ) {
-        if let Some(InteractionData::ApplicationCommand(command_data)) = interaction.data {
-            let channel = match interaction.channel {
-                Some(c) => c,
-                None => {
-                    log::warn!("Received a command from an unknown channel.");
+        let user_id = interaction.author_id();
+        let member_permissions = interaction.member.as_ref().and_then(|m| m.permissions);
+        let channel = match interaction.channel {
+            Some(c) => c,
+            None => {
+                log::warn!("Received an interaction from an unknown channel.");
+                return;
+            }
+        };
+
+        let command_handler_data = CommandHandlerData {
+            channel,
+            guild_id: interaction.guild_id,
+            user_id,
+            member_permissions,
+            interaction_client: self.twilight_client.interaction(application_id),
+            reqwest_client: self.reqwest_client.to_owned(),
+            twilight_client: &self.twilight_client,
+            storage: self.storage.clone(),
+        };
+
+        match interaction.data {
+            Some(InteractionData::ApplicationCommand(command_data)) => {
+                if interaction.kind == InteractionType::ApplicationCommandAutocomplete {
+                    if command_data.name.as_str() == "horde-chat" {
+                        if let Ok(autocomplete) =
+                            HordeChatAutocomplete::from_interaction((*command_data).into())
+                        {
+                            autocomplete
+                                .handle_autocomplete(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
+                    }
                     return;
                 }
-            };
-
-            let command_handler_data = CommandHandlerData {
-                channel,
-                interaction_client: self.twilight_client.interaction(application_id),
-                reqwest_client: self.reqwest_client.to_owned(),
-                twilight_client: &self.twilight_client,
-            };
-
-            match command_data.name.as_str() {
-                "horde" => {
-                    if let Ok(horde_command) =
-                        HordeCommand::from_interaction((*command_data).into())
-                    {
-                        horde_command
-                            .handle_command(
-                                command_handler_data,
-                                interaction.id,
-                                &interaction.token,
-                            )
-                            .await
+
+                match command_data.name.as_str() {
+                    "horde" => {
+                        if let Ok(horde_command) =
+                            HordeCommand::from_interaction((*command_data).into())
+                        {
+                            horde_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
                     }
-                }
-                "dream" => {
-                    if let Ok(dream_command) =
-                        DreamCommand::from_interaction((*command_data).into())
-                    {
-                        dream_command
-                            .handle_command(
-                                command_handler_data,
-                                interaction.id,
-                                &interaction.token,
-                            )
-                            .await
+                    "dream" => {
+                        if let Ok(dream_command) =
+                            DreamCommand::from_interaction((*command_data).into())
+                        {
+                            dream_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
                     }
-                }
-                "info" => {
-                    if let Ok(info_command) = InfoCommand::from_interaction((*command_data).into())
-                    {
-                        info_command
-                            .handle_command(
-                                command_handler_data,
-                                interaction.id,
-                                &interaction.token,
-                            )
-                            .await
+                    "info" => {
+                        if let Ok(info_command) =
+                            InfoCommand::from_interaction((*command_data).into())
+                        {
+                            info_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
                     }
-                }
-                "chat" => {
-                    if let Ok(chat_command) = ChatCommand::from_interaction((*command_data).into())
-                    {
-                        chat_command
-                            .handle_command(
-                                command_handler_data,
-                                interaction.id,
-                                &interaction.token,
-                            )
-                            .await
+                    "chat" => {
+                        if let Ok(chat_command) =
+                            ChatCommand::from_interaction((*command_data).into())
+                        {
+                            chat_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
                     }
-                }
-                "nano" => {
-                    if let Ok(nano_command) = NanoCommand::from_interaction((*command_data).into())
-                    {
-                        nano_command
-                            .handle_command(
-                                command_handler_data,
-                                interaction.id,
-                                &interaction.token,
-                            )
-                            .await
+                    "nano" => {
+                        if let Ok(nano_command) =
+                            NanoCommand::from_interaction((*command_data).into())
+                        {
+                            nano_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
+                    }
+                    "stats" => {
+                        if let Ok(stats_command) =
+                            StatsCommand::from_interaction((*command_data).into())
+                        {
+                            stats_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
+                    }
+                    "describe" => {
+                        if let Ok(describe_command) =
+                            DescribeCommand::from_interaction((*command_data).into())
+                        {
+                            describe_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
+                    }
+                    "horde-chat" => {
+                        if let Ok(horde_chat_command) =
+                            HordeChatCommand::from_interaction((*command_data).into())
+                        {
+                            horde_chat_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
                     }
+                    "safety" => {
+                        if let Ok(safety_command) =
+                            SafetyCommand::from_interaction((*command_data).into())
+                        {
+                            safety_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
+                    }
+                    "keys" => {
+                        if let Ok(keys_command) =
+                            KeysCommand::from_interaction((*command_data).into())
+                        {
+                            keys_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
+                    }
+                    "usage" => {
+                        if let Ok(usage_command) =
+                            UsageCommand::from_interaction((*command_data).into())
+                        {
+                            usage_command
+                                .handle_command(
+                                    command_handler_data,
+                                    interaction.id,
+                                    &interaction.token,
+                                )
+                                .await
+                        }
+                    }
+                    nano::EDIT_COMMAND_NAME => {
+                        nano::handle_edit_command(
+                            command_handler_data,
+                            &command_data,
+                            interaction.id,
+                            &interaction.token,
+                        )
+                        .await
+                    }
+                    &_ => {}
                 }
-                "stats" => {
-                    if let Ok(stats_command) =
-                        StatsCommand::from_interaction((*command_data).into())
-                    {
-                        stats_command
-                            .handle_command(
-                                command_handler_data,
-                                interaction.id,
-                                &interaction.token,
-                            )
+            }
+            Some(InteractionData::MessageComponent(component_data))
+                if component_data.custom_id.starts_with("nano:") =>
+            {
+                nano::handle_component(
+                    command_handler_data,
+                    &component_data.custom_id,
+                    interaction.id,
+                    &interaction.token,
+                )
+                .await
+            }
+            Some(InteractionData::MessageComponent(component_data))
+                if component_data.custom_id.starts_with("stats:") =>
+            {
+                stats::handle_component(
+                    command_handler_data,
+                    &component_data.custom_id,
+                    interaction.id,
+                    &interaction.token,
+                )
+                .await
+            }
+            Some(InteractionData::ModalSubmit(modal_data))
+                if modal_data.custom_id.starts_with("nano:") =>
+            {
+                nano::handle_modal(
+                    command_handler_data,
+                    &modal_data,
+                    interaction.id,
+                    &interaction.token,
+                )
+                .await
+            }
+            _ => {}
+        }
+    }
+
+    async fn resume_jobs(&self, application_id: Id<ApplicationMarker>) {
+        let jobs = match self.storage.pending_jobs() {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                log::error!("Failed to load pending jobs: {}", e);
+                return;
+            }
+        };
+
+        let interaction_client = self.twilight_client.interaction(application_id);
+        let mut resumed = Vec::new();
+
+        for job in jobs {
+            if unix_now().saturating_sub(job.origin.created_at) > INTERACTION_TOKEN_LIFETIME {
+                log::info!("Dropping job {}, its interaction has expired", job.id);
+                self.storage.remove_job(job.id).ok();
+                continue;
+            }
+
+            let reqwest_client = &self.reqwest_client;
+            let storage = &self.storage;
+            let interaction_client = &interaction_client;
+            resumed.push(async move {
+                match job.kind {
+                    JobKind::Horde => {
+                        horde::resume_job(reqwest_client, storage, job, interaction_client).await
+                    }
+                    JobKind::Chat => {
+                        chat::resume_job(reqwest_client, storage, job, interaction_client).await
+                    }
+                    JobKind::Describe => {
+                        describe::resume_job(reqwest_client, storage, job, interaction_client).await
+                    }
+                    JobKind::HordeText => {
+                        horde_chat::resume_job(reqwest_client, storage, job, interaction_client)
                             .await
                     }
                 }
-                &_ => {}
-            }
+            });
+        }
+
+        if !resumed.is_empty() {
+            log::info!("Resuming {} unfinished jobs.", resumed.len());
         }
+        futures::future::join_all(resumed).await;
+    }
+
+    async fn handle_message(&self, message: Message) {
+        stats::ingest_message(&self.storage, &message);
     }
 }
@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use reqwest::Client;
 use reqwest_eventsource::{Event, EventSource};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::env;
 use std::time::Duration;
@@ -16,9 +16,14 @@ use twilight_model::id::marker::InteractionMarker;
 use twilight_model::id::Id;
 use twilight_util::builder::embed::{EmbedFieldBuilder, EmbedFooterBuilder};
 
+use crate::ledger::{CallUsage, ProviderCall};
+use crate::storage::{unix_now, Job, JobKind, JobOrigin, Storage};
 use crate::utils::embed;
+use crate::utils::retry::{self, Idempotency, PendingEmbedNotice};
 
-use super::{CommandHandler, CommandHandlerData};
+use super::{job_origin, CommandHandler, CommandHandlerData, INTERACTION_TOKEN_LIFETIME};
+
+const CHAT_MODEL: &str = "qwen/qwen3-235b-a22b-instruct-2507";
 
 #[derive(CommandModel, CreateCommand)]
 #[command(name = "chat", desc = "Chat with Snowflake Arctic")]
@@ -37,6 +42,7 @@ impl CommandHandler for ChatCommand {
     ) {
         let interaction_client = command_handler_data.interaction_client;
         let reqwest_client = command_handler_data.reqwest_client;
+        let storage = command_handler_data.storage;
 
         let prompt = &self.prompt;
 
@@ -57,19 +63,40 @@ impl CommandHandler for ChatCommand {
             .await
             .ok();
 
+        let call = ProviderCall::start(
+            "chat",
+            "replicate",
+            CHAT_MODEL,
+            command_handler_data.user_id.map(Id::get),
+            command_handler_data.guild_id.map(Id::get),
+        );
         let e = match chat(
             prompt,
             &reqwest_client,
+            &storage,
             &interaction_client,
             interaction_token,
+            job_origin(
+                command_handler_data.user_id,
+                command_handler_data.guild_id,
+                interaction_id,
+            ),
         )
         .await
         {
             Ok(_) => {
+                call.finish(
+                    &storage,
+                    CallUsage {
+                        success: true,
+                        ..Default::default()
+                    },
+                );
                 return;
             }
             Err(e) => e,
         };
+        call.finish(&storage, CallUsage::failed());
 
         interaction_client
             .update_response(interaction_token)
@@ -93,6 +120,18 @@ struct ReplicateSubmit {
     urls: Urls,
 }
 
+#[derive(Deserialize)]
+struct ReplicatePrediction {
+    status: String,
+    output: Option<Vec<String>>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChatJobParams {
+    prompt: String,
+}
+
 struct ChatError {
     message: String,
 }
@@ -100,26 +139,48 @@ struct ChatError {
 async fn chat(
     prompt: &str,
     reqwest_client: &Client,
+    storage: &Storage,
     interaction_client: &InteractionClient<'_>,
     interaction_token: &str,
+    origin: JobOrigin,
 ) -> Result<(), ChatError> {
-    let submit_response = match reqwest_client
-        .post("https://api.replicate.com/v1/models/qwen/qwen3-235b-a22b-instruct-2507/predictions")
-        .header(
-            "Authorization",
-            format!("Bearer {}", env::var("REPLICATE_TOKEN").unwrap()),
-        )
-        .header("Content-Type", "application/json")
-        .body(
-            json!({
-                "input": { "prompt": prompt },
-                "stream": true
-            })
-            .to_string(),
-        )
-        .send()
-        .await
-    {
+    let retry_notice = PendingEmbedNotice {
+        interaction_client,
+        interaction_token,
+        render: |retry_field| {
+            vec![embed::pending("Chatting", "")
+                .field(EmbedFieldBuilder::new("Prompt", prompt))
+                .field(retry_field)
+                .build()]
+        },
+    };
+
+    let submit_request = retry::send(
+        || {
+            reqwest_client
+                .post(format!(
+                    "https://api.replicate.com/v1/models/{}/predictions",
+                    CHAT_MODEL
+                ))
+                .header(
+                    "Authorization",
+                    format!("Bearer {}", env::var("REPLICATE_TOKEN").unwrap()),
+                )
+                .header("Content-Type", "application/json")
+                .body(
+                    json!({
+                        "input": { "prompt": prompt },
+                        "stream": true
+                    })
+                    .to_string(),
+                )
+        },
+        Idempotency::NonIdempotent,
+        &retry_notice,
+    )
+    .await;
+
+    let submit_response = match submit_request {
         Ok(r) => match r.json::<ReplicateSubmit>().await {
             Ok(j) => j,
             Err(e) => {
@@ -138,6 +199,52 @@ async fn chat(
     let stream_url = &submit_response.urls.stream;
     let prediction_id = &submit_response.id;
 
+    let job_params = serde_json::to_string(&ChatJobParams {
+        prompt: prompt.to_string(),
+    })
+    .unwrap_or_default();
+
+    let job_id = match storage.insert_job(
+        JobKind::Chat,
+        interaction_token,
+        origin,
+        prediction_id,
+        &job_params,
+    ) {
+        Ok(job_id) => Some(job_id),
+        Err(e) => {
+            log::warn!("Failed to record chat job {}: {}", prediction_id, e);
+            None
+        }
+    };
+
+    let result = stream_output(
+        prompt,
+        stream_url,
+        prediction_id,
+        reqwest_client,
+        interaction_client,
+        interaction_token,
+    )
+    .await;
+
+    if let Some(job_id) = job_id {
+        if let Err(e) = storage.remove_job(job_id) {
+            log::warn!("Failed to remove chat job {}: {}", job_id, e);
+        }
+    }
+
+    result
+}
+
+async fn stream_output(
+    prompt: &str,
+    stream_url: &str,
+    prediction_id: &str,
+    reqwest_client: &Client,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+) -> Result<(), ChatError> {
     let mut es =
         EventSource::new(reqwest_client.get(stream_url)).expect("Failed to create event source");
 
@@ -198,6 +305,147 @@ async fn chat(
         }
     }
 
+    send_final_output(
+        prompt,
+        prediction_id,
+        full_output,
+        interaction_client,
+        interaction_token,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Picks up a chat prediction that was still streaming when the bot stopped.
+/// The stream cannot be rejoined, so the prediction is polled until Replicate
+/// reports a final state.
+pub async fn resume_job(
+    reqwest_client: &Client,
+    storage: &Storage,
+    job: Job,
+    interaction_client: &InteractionClient<'_>,
+) {
+    let params: ChatJobParams = match serde_json::from_str(&job.params) {
+        Ok(p) => p,
+        Err(e) => {
+            log::error!("Discarding chat job {} with invalid params: {}", job.id, e);
+            storage.remove_job(job.id).ok();
+            return;
+        }
+    };
+
+    log::info!("Resuming chat prediction {}", job.provider_job_id);
+
+    let call = ProviderCall::start(
+        "chat",
+        "replicate",
+        CHAT_MODEL,
+        job.origin.user_id,
+        job.origin.guild_id,
+    );
+    let result = poll_prediction(reqwest_client, &job).await;
+    call.finish(
+        storage,
+        match result {
+            Ok(_) => CallUsage {
+                success: true,
+                ..Default::default()
+            },
+            Err(_) => CallUsage::failed(),
+        },
+    );
+
+    if let Err(e) = storage.remove_job(job.id) {
+        log::warn!("Failed to remove chat job {}: {}", job.id, e);
+    }
+
+    match result {
+        Ok(output) => {
+            send_final_output(
+                &params.prompt,
+                &job.provider_job_id,
+                output,
+                interaction_client,
+                &job.interaction_token,
+            )
+            .await
+        }
+        Err(e) => {
+            interaction_client
+                .update_response(&job.interaction_token)
+                .embeds(Some(&[
+                    embed::prompt(&params.prompt).build(),
+                    embed::failure(&e.message).build(),
+                ]))
+                .await
+                .ok();
+        }
+    }
+}
+
+async fn poll_prediction(reqwest_client: &Client, job: &Job) -> Result<String, ChatError> {
+    loop {
+        if unix_now().saturating_sub(job.origin.created_at) > INTERACTION_TOKEN_LIFETIME {
+            return Err(ChatError {
+                message: "The prediction did not finish before the interaction expired".to_string(),
+            });
+        }
+
+        let poll_request = retry::send(
+            || {
+                reqwest_client
+                    .get(format!(
+                        "https://api.replicate.com/v1/predictions/{}",
+                        job.provider_job_id
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", env::var("REPLICATE_TOKEN").unwrap()),
+                    )
+            },
+            Idempotency::Idempotent,
+            &(),
+        )
+        .await;
+
+        let prediction = match poll_request {
+            Ok(r) => match r.json::<ReplicatePrediction>().await {
+                Ok(p) => p,
+                Err(e) => {
+                    return Err(ChatError {
+                        message: format!("Failed to parse prediction: {:#?}", e),
+                    })
+                }
+            },
+            Err(e) => {
+                return Err(ChatError {
+                    message: format!("Failed to fetch prediction: {:#?}", e),
+                })
+            }
+        };
+
+        match prediction.status.as_str() {
+            "succeeded" => return Ok(prediction.output.unwrap_or_default().concat()),
+            "failed" | "canceled" => {
+                return Err(ChatError {
+                    message: prediction
+                        .error
+                        .unwrap_or_else(|| format!("The prediction was {}", prediction.status)),
+                })
+            }
+            _ => tokio::time::sleep(Duration::from_secs(2)).await,
+        }
+    }
+}
+
+async fn send_final_output(
+    prompt: &str,
+    prediction_id: &str,
+    mut full_output: String,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+) {
     if full_output.is_empty() {
         interaction_client
             .update_response(interaction_token)
@@ -230,6 +478,4 @@ async fn chat(
             .await
             .ok();
     }
-
-    Ok(())
 }
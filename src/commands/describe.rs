@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use twilight_http::client::InteractionClient;
+use twilight_interactions::command::{CommandModel, CreateCommand};
+use twilight_model::channel::message::{Embed, MessageFlags};
+use twilight_model::channel::Attachment;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::embed::{EmbedFieldBuilder, EmbedFooterBuilder, ImageSource};
+
+use super::{job_origin, CommandHandler, CommandHandlerData};
+use crate::ledger::{CallUsage, ProviderCall};
+use crate::storage::{Job, JobKind, JobOrigin, Storage};
+use crate::utils::embed;
+use crate::utils::horde::{submit_async, HordeError, HORDE_API_URL, HORDE_TIMEOUT_SECS};
+use crate::utils::retry::{self, Idempotency, PendingEmbedNotice};
+
+const MAX_TAGS: usize = 12;
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "describe",
+    desc = "Caption and tag an image with the Stable Horde 👺"
+)]
+pub struct DescribeCommand {
+    /// Image to describe
+    image: Attachment,
+}
+
+// Submit request
+#[derive(Serialize)]
+struct InterrogateForm<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct InterrogateSubmit<'a> {
+    forms: Vec<InterrogateForm<'a>>,
+    source_image: &'a str,
+}
+
+// Polling
+#[derive(Deserialize)]
+struct InterrogatePoll {
+    state: String,
+    #[serde(default)]
+    forms: Vec<InterrogateFormStatus>,
+}
+
+#[derive(Deserialize)]
+struct InterrogateFormStatus {
+    form: String,
+    result: Option<InterrogateResult>,
+}
+
+#[derive(Deserialize)]
+struct InterrogateResult {
+    caption: Option<String>,
+    nsfw: Option<bool>,
+    // Keyed by category (tags, artists, flavors, mediums, ...)
+    interrogation: Option<HashMap<String, Vec<InterrogateTerm>>>,
+}
+
+#[derive(Deserialize)]
+struct InterrogateTerm {
+    text: String,
+    confidence: f32,
+}
+
+#[derive(Default)]
+struct Description {
+    caption: Option<String>,
+    tags: Vec<InterrogateTerm>,
+    nsfw: Option<bool>,
+}
+
+// Persisted with the job so polling can resume
+#[derive(Serialize, Deserialize)]
+struct DescribeDetails {
+    image_url: String,
+}
+
+#[async_trait]
+impl CommandHandler for DescribeCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str,
+    ) {
+        let interaction_client = command_handler_data.interaction_client;
+        let reqwest_client = command_handler_data.reqwest_client;
+        let storage = command_handler_data.storage;
+
+        // Horde only takes images, and would fail other files after queueing them
+        let is_image = self
+            .image
+            .content_type
+            .as_deref()
+            .is_some_and(|t| t.starts_with("image/"));
+        if !is_image {
+            interaction_client
+                .create_response(
+                    interaction_id,
+                    interaction_token,
+                    &InteractionResponse {
+                        kind: InteractionResponseType::ChannelMessageWithSource,
+                        data: Some(InteractionResponseData {
+                            embeds: Some(vec![embed::failure(&format!(
+                                "`{}` is not an image.",
+                                self.image.filename
+                            ))
+                            .build()]),
+                            flags: Some(MessageFlags::EPHEMERAL),
+                            ..Default::default()
+                        }),
+                    },
+                )
+                .await
+                .ok();
+            return;
+        }
+
+        let details = DescribeDetails {
+            image_url: self.image.url.clone(),
+        };
+
+        interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        embeds: Some(vec![embed::pending("Submitting", "")
+                            .thumbnail(ImageSource::url(&details.image_url).unwrap())
+                            .build()]),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await
+            .ok();
+
+        let call = ProviderCall::start(
+            "describe",
+            "horde",
+            "interrogation",
+            command_handler_data.user_id.map(Id::get),
+            command_handler_data.guild_id.map(Id::get),
+        );
+        let result = describe(
+            &reqwest_client,
+            &storage,
+            &details,
+            &interaction_client,
+            interaction_token,
+            job_origin(
+                command_handler_data.user_id,
+                command_handler_data.guild_id,
+                interaction_id,
+            ),
+        )
+        .await;
+        call.finish(
+            &storage,
+            CallUsage {
+                success: result.is_ok(),
+                ..Default::default()
+            },
+        );
+
+        if let Err(e) = result {
+            interaction_client
+                .update_response(interaction_token)
+                .embeds(Some(&[failure_embed(&e.message, &details)]))
+                .await
+                .ok();
+        }
+    }
+}
+
+fn failure_embed(message: &str, details: &DescribeDetails) -> Embed {
+    embed::failure(message)
+        .thumbnail(ImageSource::url(&details.image_url).unwrap())
+        .build()
+}
+
+/// Picks up an interrogation that was still being polled when the bot
+/// stopped, and updates the original interaction once it finishes.
+pub async fn resume_job(
+    reqwest_client: &Client,
+    storage: &Storage,
+    job: Job,
+    interaction_client: &InteractionClient<'_>,
+) {
+    let details: DescribeDetails = match serde_json::from_str(&job.params) {
+        Ok(d) => d,
+        Err(e) => {
+            log::error!(
+                "Discarding describe job {} with invalid params: {}",
+                job.id,
+                e
+            );
+            storage.remove_job(job.id).ok();
+            return;
+        }
+    };
+
+    log::info!("Resuming interrogation {}", job.provider_job_id);
+
+    let call = ProviderCall::start(
+        "describe",
+        "horde",
+        "interrogation",
+        job.origin.user_id,
+        job.origin.guild_id,
+    );
+    let result = finish_interrogation(
+        reqwest_client,
+        &job.provider_job_id,
+        &details,
+        UNIX_EPOCH + Duration::from_secs(job.origin.created_at),
+        interaction_client,
+        &job.interaction_token,
+    )
+    .await;
+
+    call.finish(
+        storage,
+        CallUsage {
+            success: result.is_ok(),
+            ..Default::default()
+        },
+    );
+
+    if let Err(e) = storage.remove_job(job.id) {
+        log::warn!("Failed to remove describe job {}: {}", job.id, e);
+    }
+
+    if let Err(e) = result {
+        interaction_client
+            .update_response(&job.interaction_token)
+            .embeds(Some(&[failure_embed(&e.message, &details)]))
+            .await
+            .ok();
+    }
+}
+
+async fn describe(
+    reqwest_client: &Client,
+    storage: &Storage,
+    details: &DescribeDetails,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+    origin: JobOrigin,
+) -> Result<(), HordeError> {
+    let retry_notice = PendingEmbedNotice {
+        interaction_client,
+        interaction_token,
+        render: |retry_field| {
+            vec![embed::pending("Submitting", "")
+                .thumbnail(ImageSource::url(&details.image_url).unwrap())
+                .field(retry_field)
+                .build()]
+        },
+    };
+
+    let id = submit_async(
+        reqwest_client,
+        "interrogate/async",
+        &InterrogateSubmit {
+            forms: vec![
+                InterrogateForm { name: "caption" },
+                InterrogateForm {
+                    name: "interrogation",
+                },
+                InterrogateForm { name: "nsfw" },
+            ],
+            source_image: &details.image_url,
+        },
+        &retry_notice,
+    )
+    .await?;
+
+    interaction_client
+        .update_response(interaction_token)
+        .embeds(Some(&[embed::pending("Submitted", "")
+            .thumbnail(ImageSource::url(&details.image_url).unwrap())
+            .footer(EmbedFooterBuilder::new(&id))
+            .build()]))
+        .await
+        .ok();
+
+    let job_params = serde_json::to_string(details).unwrap_or_default();
+
+    let job_id = match storage.insert_job(
+        JobKind::Describe,
+        interaction_token,
+        origin,
+        &id,
+        &job_params,
+    ) {
+        Ok(job_id) => Some(job_id),
+        Err(e) => {
+            log::warn!("Failed to record describe job {}: {}", id, e);
+            None
+        }
+    };
+
+    let result = finish_interrogation(
+        reqwest_client,
+        &id,
+        details,
+        SystemTime::now(),
+        interaction_client,
+        interaction_token,
+    )
+    .await;
+
+    if let Some(job_id) = job_id {
+        if let Err(e) = storage.remove_job(job_id) {
+            log::warn!("Failed to remove describe job {}: {}", job_id, e);
+        }
+    }
+
+    result
+}
+
+async fn finish_interrogation(
+    reqwest_client: &Client,
+    id: &str,
+    details: &DescribeDetails,
+    start: SystemTime,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+) -> Result<(), HordeError> {
+    let description = poll_status(
+        reqwest_client,
+        id,
+        details,
+        start,
+        interaction_client,
+        interaction_token,
+    )
+    .await?;
+
+    let mut success = embed::success()
+        .thumbnail(ImageSource::url(&details.image_url).unwrap())
+        .footer(EmbedFooterBuilder::new(id));
+
+    if let Some(caption) = description.caption {
+        success = success.field(EmbedFieldBuilder::new("Caption", caption));
+    }
+
+    if !description.tags.is_empty() {
+        let tags = description
+            .tags
+            .iter()
+            .map(|t| format!("`{}` ({:.0}%)", t.text, t.confidence))
+            .collect::<Vec<String>>()
+            .join(", ");
+        success = success.field(EmbedFieldBuilder::new("Tags", tags));
+    }
+
+    if let Some(nsfw) = description.nsfw {
+        success = success.field(EmbedFieldBuilder::new(
+            "NSFW",
+            match nsfw {
+                true => "True",
+                false => "False",
+            },
+        ));
+    }
+
+    interaction_client
+        .update_response(interaction_token)
+        .embeds(Some(&[success.build()]))
+        .await
+        .ok();
+
+    Ok(())
+}
+
+async fn poll_status(
+    reqwest_client: &Client,
+    id: &str,
+    details: &DescribeDetails,
+    start: SystemTime,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+) -> Result<Description, HordeError> {
+    let retry_notice = PendingEmbedNotice {
+        interaction_client,
+        interaction_token,
+        render: |retry_field| {
+            vec![embed::pending("Pending", "")
+                .thumbnail(ImageSource::url(&details.image_url).unwrap())
+                .field(retry_field)
+                .footer(EmbedFooterBuilder::new(id))
+                .build()]
+        },
+    };
+
+    let mut last_state = String::new();
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let since_start = SystemTime::now()
+            .duration_since(start)
+            .expect("Time went backwards");
+
+        if since_start.as_secs() > HORDE_TIMEOUT_SECS {
+            return Err(HordeError {
+                message: "The command timed out after 3 minutes".to_string(),
+            });
+        }
+
+        let poll_request = retry::send(
+            || reqwest_client.get(format!("{}/interrogate/status/{}", HORDE_API_URL, id)),
+            Idempotency::Idempotent,
+            &retry_notice,
+        )
+        .await;
+
+        let poll_response = match poll_request {
+            Ok(r) => match r.json::<InterrogatePoll>().await {
+                Ok(j) => j,
+                Err(e) => {
+                    log::warn!("Failed to parse interrogation status: {:#?}", e);
+                    continue;
+                }
+            },
+            Err(e) => {
+                return Err(HordeError {
+                    message: format!("{:#?}", e),
+                })
+            }
+        };
+
+        match poll_response.state.as_str() {
+            "done" => return Ok(collect_description(poll_response.forms)),
+            "faulted" | "cancelled" => {
+                return Err(HordeError {
+                    message: format!("The interrogation was {}", poll_response.state),
+                })
+            }
+            state if state != last_state => {
+                interaction_client
+                    .update_response(interaction_token)
+                    .embeds(Some(&[embed::pending("Pending", "")
+                        .thumbnail(ImageSource::url(&details.image_url).unwrap())
+                        .field(EmbedFieldBuilder::new("Status", format!("**{}**", state)))
+                        .footer(EmbedFooterBuilder::new(id))
+                        .build()]))
+                    .await
+                    .ok();
+                last_state = state.to_string();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_description(forms: Vec<InterrogateFormStatus>) -> Description {
+    let mut description = Description::default();
+
+    for form in forms {
+        let Some(result) = form.result else {
+            continue;
+        };
+
+        match form.form.as_str() {
+            "caption" => description.caption = result.caption,
+            "nsfw" => description.nsfw = result.nsfw,
+            "interrogation" => {
+                let mut terms: Vec<InterrogateTerm> = result
+                    .interrogation
+                    .unwrap_or_default()
+                    .into_values()
+                    .flatten()
+                    .collect();
+                terms.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+                terms.truncate(MAX_TAGS);
+                description.tags = terms;
+            }
+            _ => {}
+        }
+    }
+
+    description
+}
@@ -2,9 +2,8 @@ use async_trait::async_trait;
 use base64::engine::general_purpose;
 use base64::Engine;
 use reqwest::Client;
-use serde::Deserialize;
-use serde_json::json;
 use twilight_interactions::command::{CommandModel, CommandOption, CreateCommand, CreateOption};
+use twilight_model::channel::message::MessageFlags;
 use twilight_model::http::attachment::Attachment;
 use twilight_model::http::interaction::{
     InteractionResponse, InteractionResponseData, InteractionResponseType,
@@ -14,8 +13,15 @@ use twilight_model::id::Id;
 use twilight_util::builder::embed::{EmbedFieldBuilder, EmbedFooterBuilder, ImageSource};
 
 use super::{CommandHandler, CommandHandlerData};
+use crate::ledger::{CallUsage, ProviderCall};
+use crate::quota::{self, Provider};
 use crate::utils::embed;
-use crate::utils::google_ai::{post_generative_ai, GoogleAiError, GOOGLE_API_PAID_KEY};
+use crate::utils::google_ai::{
+    GoogleAiClient, PredictInstance, PredictParameters, PredictRequest, GOOGLE_API_PAID_KEY,
+};
+use crate::utils::retry::{PendingEmbedNotice, RetryObserver};
+
+const IMAGEN_MODEL: &str = "imagen-4.0-generate-001";
 
 #[derive(CommandOption, CreateOption)]
 enum ImagenAspectRatio {
@@ -41,17 +47,6 @@ struct DreamParams<'a> {
     aspect_ratio: &'a str,
 }
 
-#[derive(Deserialize)]
-struct ImagenResponse {
-    predictions: Vec<ImagenPrediction>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ImagenPrediction {
-    bytes_base64_encoded: String,
-}
-
 #[async_trait]
 impl CommandHandler for DreamCommand {
     async fn handle_command(
@@ -75,6 +70,33 @@ impl CommandHandler for DreamCommand {
             aspect_ratio,
         };
 
+        let quota_ticket = match quota::acquire(
+            &command_handler_data.storage,
+            Provider::Imagen,
+            command_handler_data.user_id.map(Id::get),
+            command_handler_data.guild_id.map(Id::get),
+        ) {
+            Ok(ticket) => ticket,
+            Err(exceeded) => {
+                interaction_client
+                    .create_response(
+                        interaction_id,
+                        interaction_token,
+                        &InteractionResponse {
+                            kind: InteractionResponseType::ChannelMessageWithSource,
+                            data: Some(InteractionResponseData {
+                                embeds: Some(vec![exceeded.embed()]),
+                                flags: Some(MessageFlags::EPHEMERAL),
+                                ..Default::default()
+                            }),
+                        },
+                    )
+                    .await
+                    .ok();
+                return;
+            }
+        };
+
         interaction_client
             .create_response(
                 interaction_id,
@@ -93,10 +115,38 @@ impl CommandHandler for DreamCommand {
             .await
             .ok();
 
-        match dream(&reqwest_client, &dream_params).await {
+        let retry_notice = PendingEmbedNotice {
+            interaction_client: &interaction_client,
+            interaction_token,
+            render: |retry_field| {
+                vec![embed::pending("Dreaming", "")
+                    .field(EmbedFieldBuilder::new("Prompt", prompt))
+                    .field(details_field(&dream_params))
+                    .field(retry_field)
+                    .build()]
+            },
+        };
+
+        let call = ProviderCall::start(
+            "dream",
+            "google",
+            IMAGEN_MODEL,
+            command_handler_data.user_id.map(Id::get),
+            command_handler_data.guild_id.map(Id::get),
+        );
+        match dream(&reqwest_client, &dream_params, &retry_notice).await {
             Ok((image, tier_used)) => {
+                call.finish(
+                    &command_handler_data.storage,
+                    CallUsage {
+                        success: true,
+                        tier: Some(tier_used),
+                        images: 1,
+                        ..Default::default()
+                    },
+                );
                 let filename = "image.png".to_string();
-                let footer_text = format!("Model: imagen-4.0-generate-001 | Tier: {}", tier_used);
+                let footer_text = format!("Model: {} | Tier: {}", IMAGEN_MODEL, tier_used);
                 let footer = EmbedFooterBuilder::new(footer_text).build();
 
                 interaction_client
@@ -117,6 +167,8 @@ impl CommandHandler for DreamCommand {
                     .ok();
             }
             Err(e) => {
+                call.finish(&command_handler_data.storage, CallUsage::failed());
+                quota_ticket.refund(&command_handler_data.storage);
                 interaction_client
                     .update_response(interaction_token)
                     .embeds(Some(&[embed::failure(&e.message)
@@ -141,50 +193,49 @@ fn details_field(dream_params: &DreamParams) -> EmbedFieldBuilder {
 async fn dream(
     reqwest_client: &Client,
     dream_params: &DreamParams<'_>,
+    retry_observer: &dyn RetryObserver,
 ) -> Result<(Vec<u8>, &'static str), DreamError> {
     let prompt = dream_params.prompt;
     let aspect_ratio = dream_params.aspect_ratio;
 
-    let request_body = json!({
-        "instances": [
-            { "prompt": prompt }
-        ],
-        "parameters": {
-            "sampleCount": 1,
-            "aspectRatio": aspect_ratio,
-            "personGeneration": "allow_all"
-        }
-    });
-
-    let api_url =
-        "https://generativelanguage.googleapis.com/v1beta/models/imagen-4.0-generate-001:predict";
-
-    let google_ai_response = post_generative_ai(
-        reqwest_client,
-        api_url,
-        &request_body,
-        &[GOOGLE_API_PAID_KEY],
-    )
-    .await
-    .map_err(|e: GoogleAiError| DreamError { message: e.message })?;
-    let text = google_ai_response.text;
+    let request = PredictRequest {
+        instances: vec![PredictInstance {
+            prompt: prompt.to_string(),
+        }],
+        parameters: PredictParameters {
+            sample_count: 1,
+            aspect_ratio: Some(aspect_ratio.to_string()),
+            person_generation: Some("allow_all".to_string()),
+        },
+    };
+
+    let google_ai_response = GoogleAiClient::new(reqwest_client, &[GOOGLE_API_PAID_KEY])
+        .with_retry_observer(retry_observer)
+        .predict(IMAGEN_MODEL, &request)
+        .await
+        .map_err(|e| DreamError { message: e.message })?;
     let tier_used = google_ai_response.tier_used;
 
-    let imagen_response: ImagenResponse = serde_json::from_str(&text).map_err(|e| DreamError {
-        message: format!(
-            "JSON Parse Error with tier {} {}: \nResponse: {}",
-            tier_used, e, text
-        ),
-    })?;
-
-    let base64_image = imagen_response
+    let prediction = google_ai_response
+        .body
         .predictions
-        .get(0)
+        .into_iter()
+        .next()
         .ok_or(DreamError {
             message: "No predictions in response".to_string(),
-        })?
-        .bytes_base64_encoded
-        .clone();
+        })?;
+
+    let Some(base64_image) = prediction.bytes_base64_encoded else {
+        return Err(DreamError {
+            message: format!(
+                "Image was filtered: {}",
+                prediction
+                    .rai_filtered_reason
+                    .as_deref()
+                    .unwrap_or("no reason given")
+            ),
+        });
+    };
 
     let image = general_purpose::STANDARD
         .decode(base64_image)
@@ -1,31 +1,32 @@
-use std::env;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use base64::engine::general_purpose;
 use base64::Engine as _;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use twilight_http::client::InteractionClient;
 use twilight_interactions::command::{CommandModel, CommandOption, CreateCommand, CreateOption};
+use twilight_model::channel::message::Embed;
 use twilight_model::http::attachment::Attachment;
 use twilight_model::http::interaction::{
     InteractionResponse, InteractionResponseData, InteractionResponseType,
 };
 use twilight_model::id::marker::InteractionMarker;
 use twilight_model::id::Id;
-use twilight_util::builder::embed::{EmbedFieldBuilder, EmbedFooterBuilder, ImageSource};
+use twilight_util::builder::embed::{
+    EmbedBuilder, EmbedFieldBuilder, EmbedFooterBuilder, ImageSource,
+};
 
-use super::{CommandHandler, CommandHandlerData};
+use super::{job_origin, CommandHandler, CommandHandlerData};
+use crate::ledger::{CallUsage, ProviderCall};
+use crate::storage::{Job, JobKind, JobOrigin, Storage};
 use crate::utils::embed;
-
-#[derive(Debug, PartialEq)]
-enum Status {
-    Finished,
-    Processing,
-    Waiting,
-}
+use crate::utils::horde::{
+    submit_async, worker_count, HordeError, HordePoll, ModelType, Progress, Status, HORDE_API_URL,
+    HORDE_TIMEOUT_SECS,
+};
+use crate::utils::retry::{self, Idempotency, PendingEmbedNotice};
 
 #[derive(CommandOption, CreateOption)]
 enum DiffusionModel {
@@ -66,22 +67,6 @@ struct HordeSubmit<'a> {
     trusted_workers: bool,
 }
 
-// Initial response
-#[derive(Deserialize)]
-struct HordeResponse {
-    id: Option<String>,
-    message: Option<String>,
-}
-
-// Polling
-#[derive(Deserialize)]
-struct HordePoll {
-    done: bool,
-    faulted: bool,
-    wait_time: f32,
-    queue_position: f32,
-}
-
 // Final generation
 #[derive(Deserialize)]
 struct HordeGeneration {
@@ -94,9 +79,12 @@ struct HordeFinal {
     generations: Vec<HordeGeneration>,
 }
 
-// Error handling
-struct HordeError {
-    message: String,
+// Shown on every embed, and persisted with the job so polling can resume
+#[derive(Serialize, Deserialize)]
+struct HordeDetails {
+    prompt: String,
+    model_name: String,
+    nsfw: bool,
 }
 
 #[async_trait]
@@ -109,6 +97,7 @@ impl CommandHandler for HordeCommand {
     ) {
         let interaction_client = command_handler_data.interaction_client;
         let reqwest_client = command_handler_data.reqwest_client;
+        let storage = command_handler_data.storage;
 
         let (model_name, model_version) = match &self.model {
             Some(m) => match m {
@@ -123,6 +112,12 @@ impl CommandHandler for HordeCommand {
 
         let nsfw = self.nsfw.unwrap_or(false) && command_handler_data.channel.nsfw.unwrap_or(false);
 
+        let details = HordeDetails {
+            prompt: prompt.to_string(),
+            model_name: model_name.to_string(),
+            nsfw,
+        };
+
         interaction_client
             .create_response(
                 interaction_id,
@@ -148,32 +143,41 @@ impl CommandHandler for HordeCommand {
             .await
             .ok();
 
+        let call = ProviderCall::start(
+            "horde",
+            "horde",
+            model_name,
+            command_handler_data.user_id.map(Id::get),
+            command_handler_data.guild_id.map(Id::get),
+        );
         match horde(
             &reqwest_client,
-            prompt,
-            model_name,
+            &storage,
+            &details,
             model_version,
-            nsfw,
             &interaction_client,
             interaction_token,
+            job_origin(
+                command_handler_data.user_id,
+                command_handler_data.guild_id,
+                interaction_id,
+            ),
         )
         .await
         {
-            Ok(_) => return,
+            Ok(_) => call.finish(
+                &storage,
+                CallUsage {
+                    success: true,
+                    images: 1,
+                    ..Default::default()
+                },
+            ),
             Err(e) => {
+                call.finish(&storage, CallUsage::failed());
                 interaction_client
                     .update_response(interaction_token)
-                    .embeds(Some(&[embed::failure(&e.message)
-                        .field(EmbedFieldBuilder::new("Prompt", prompt))
-                        .field(EmbedFieldBuilder::new("Model", model_name))
-                        .field(EmbedFieldBuilder::new(
-                            "NSFW",
-                            match nsfw {
-                                true => "True",
-                                false => "False",
-                            },
-                        ))
-                        .build()]))
+                    .embeds(Some(&[failure_embed(&e.message, &details)]))
                     .await
                     .ok();
             }
@@ -181,60 +185,139 @@ impl CommandHandler for HordeCommand {
     }
 }
 
+fn pending_embed(title: &str, details: &HordeDetails) -> EmbedBuilder {
+    embed::pending(title, "")
+        .field(EmbedFieldBuilder::new("Prompt", &details.prompt))
+        .field(EmbedFieldBuilder::new("Model", &details.model_name))
+        .field(EmbedFieldBuilder::new(
+            "NSFW",
+            match details.nsfw {
+                true => "True",
+                false => "False",
+            },
+        ))
+}
+
+fn failure_embed(message: &str, details: &HordeDetails) -> Embed {
+    embed::failure(message)
+        .field(EmbedFieldBuilder::new("Prompt", &details.prompt))
+        .field(EmbedFieldBuilder::new("Model", &details.model_name))
+        .field(EmbedFieldBuilder::new(
+            "NSFW",
+            match details.nsfw {
+                true => "True",
+                false => "False",
+            },
+        ))
+        .build()
+}
+
+/// Picks up a Horde generation that was still being polled when the bot
+/// stopped, and updates the original interaction once it finishes.
+pub async fn resume_job(
+    reqwest_client: &Client,
+    storage: &Storage,
+    job: Job,
+    interaction_client: &InteractionClient<'_>,
+) {
+    let details: HordeDetails = match serde_json::from_str(&job.params) {
+        Ok(d) => d,
+        Err(e) => {
+            log::error!("Discarding Horde job {} with invalid params: {}", job.id, e);
+            storage.remove_job(job.id).ok();
+            return;
+        }
+    };
+
+    log::info!("Resuming Horde job {}", job.provider_job_id);
+
+    let call = ProviderCall::start(
+        "horde",
+        "horde",
+        &details.model_name,
+        job.origin.user_id,
+        job.origin.guild_id,
+    );
+    let result = finish_generation(
+        reqwest_client,
+        &job.provider_job_id,
+        &details,
+        Progress::new(
+            UNIX_EPOCH + Duration::from_secs(job.origin.created_at),
+            None,
+        ),
+        interaction_client,
+        &job.interaction_token,
+    )
+    .await;
+
+    call.finish(
+        storage,
+        match result {
+            Ok(_) => CallUsage {
+                success: true,
+                images: 1,
+                ..Default::default()
+            },
+            Err(_) => CallUsage::failed(),
+        },
+    );
+
+    if let Err(e) = storage.remove_job(job.id) {
+        log::warn!("Failed to remove Horde job {}: {}", job.id, e);
+    }
+
+    if let Err(e) = result {
+        interaction_client
+            .update_response(&job.interaction_token)
+            .embeds(Some(&[failure_embed(&e.message, &details)]))
+            .await
+            .ok();
+    }
+}
+
 async fn horde(
     reqwest_client: &Client,
-    prompt: &str,
-    model_name: &str,
+    storage: &Storage,
+    details: &HordeDetails,
     model_version: &str,
-    nsfw: bool,
     interaction_client: &InteractionClient<'_>,
     interaction_token: &str,
+    origin: JobOrigin,
 ) -> Result<(), HordeError> {
-    let submit_request = reqwest_client
-        .post("https://stablehorde.net/api/v2/generate/async")
-        .header("apikey", env::var("HORDE_TOKEN").unwrap())
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .body(
-            json!(&HordeSubmit {
-                prompt,
-                params: HordeParams {
-                    sampler_name: "k_euler_a",
-                    steps: 40,
-                },
-                nsfw,
-                censor_nsfw: !nsfw,
-                models: vec![model_version],
-                r2: false,
-                trusted_workers: false
-            })
-            .to_string(),
-        )
-        .send()
-        .await;
+    let prompt = &details.prompt;
+    let model_name = &details.model_name;
+    let nsfw = details.nsfw;
 
-    let id = match submit_request {
-        Ok(r) => match r.json::<HordeResponse>().await {
-            Ok(j) => match j.id {
-                Some(id) => id,
-                None => {
-                    return Err(HordeError {
-                        message: format!("{:#?}", j.message.unwrap()),
-                    })
-                }
-            },
-            Err(e) => {
-                return Err(HordeError {
-                    message: format!("{:#?}", e),
-                })
-            }
+    let retry_notice = PendingEmbedNotice {
+        interaction_client,
+        interaction_token,
+        render: |retry_field| {
+            vec![pending_embed("Submitting", details)
+                .field(retry_field)
+                .build()]
         },
-        Err(e) => {
-            return Err(HordeError {
-                message: format!("{:#?}", e),
-            })
-        }
     };
 
+    let id = submit_async(
+        reqwest_client,
+        "generate/async",
+        &HordeSubmit {
+            prompt,
+            params: HordeParams {
+                sampler_name: "k_euler_a",
+                steps: 40,
+            },
+            nsfw,
+            censor_nsfw: !nsfw,
+            models: vec![model_version],
+            r2: false,
+            trusted_workers: false,
+        },
+        &retry_notice,
+    )
+    .await?;
+
     interaction_client
         .update_response(interaction_token)
         .embeds(Some(&[embed::pending("Submitted", "")
@@ -252,12 +335,51 @@ async fn horde(
         .await
         .ok();
 
-    let generation = poll_status(
+    let job_params = serde_json::to_string(details).unwrap_or_default();
+
+    let job_id =
+        match storage.insert_job(JobKind::Horde, interaction_token, origin, &id, &job_params) {
+            Ok(job_id) => Some(job_id),
+            Err(e) => {
+                log::warn!("Failed to record Horde job {}: {}", id, e);
+                None
+            }
+        };
+
+    let workers = worker_count(reqwest_client, ModelType::Image, &[model_version]).await;
+
+    let result = finish_generation(
         reqwest_client,
         &id,
-        prompt,
-        model_name,
-        nsfw,
+        details,
+        Progress::new(SystemTime::now(), workers),
+        interaction_client,
+        interaction_token,
+    )
+    .await;
+
+    if let Some(job_id) = job_id {
+        if let Err(e) = storage.remove_job(job_id) {
+            log::warn!("Failed to remove Horde job {}: {}", job_id, e);
+        }
+    }
+
+    result
+}
+
+async fn finish_generation(
+    reqwest_client: &Client,
+    id: &str,
+    details: &HordeDetails,
+    progress: Progress,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+) -> Result<(), HordeError> {
+    let generation = poll_status(
+        reqwest_client,
+        id,
+        details,
+        progress,
         interaction_client,
         interaction_token,
     )
@@ -275,11 +397,11 @@ async fn horde(
     interaction_client
         .update_response(interaction_token)
         .embeds(Some(&[embed::success()
-            .field(EmbedFieldBuilder::new("Prompt", prompt))
-            .field(EmbedFieldBuilder::new("Model", model_name))
+            .field(EmbedFieldBuilder::new("Prompt", &details.prompt))
+            .field(EmbedFieldBuilder::new("Model", &details.model_name))
             .field(EmbedFieldBuilder::new(
                 "NSFW",
-                match nsfw {
+                match details.nsfw {
                     true => "True",
                     false => "False",
                 },
@@ -292,7 +414,7 @@ async fn horde(
                 ),
             ))
             .image(ImageSource::attachment("image.webp").unwrap())
-            .footer(EmbedFooterBuilder::new(&id))
+            .footer(EmbedFooterBuilder::new(id))
             .build()]))
         .await
         .ok();
@@ -309,33 +431,37 @@ async fn horde(
 async fn poll_status(
     reqwest_client: &Client,
     id: &str,
-    prompt: &str,
-    model_name: &str,
-    nsfw: bool,
+    details: &HordeDetails,
+    mut progress: Progress,
     interaction_client: &InteractionClient<'_>,
     interaction_token: &str,
 ) -> Result<HordeGeneration, HordeError> {
-    let start = SystemTime::now();
+    let retry_notice = PendingEmbedNotice {
+        interaction_client,
+        interaction_token,
+        render: |retry_field| {
+            vec![pending_embed("Pending", details)
+                .field(retry_field)
+                .footer(EmbedFooterBuilder::new(id))
+                .build()]
+        },
+    };
+
     loop {
         tokio::time::sleep(Duration::from_secs(1)).await;
 
-        let since_start = SystemTime::now()
-            .duration_since(start)
-            .expect("Time went backwards");
-
-        if since_start.as_secs() > 180 {
+        if progress.elapsed().as_secs() > HORDE_TIMEOUT_SECS {
             return Err(HordeError {
                 message: "The command timed out after 3 minutes".to_string(),
             });
         }
 
-        let poll_request = reqwest_client
-            .get(format!(
-                "https://stablehorde.net/api/v2/generate/check/{}",
-                &id
-            ))
-            .send()
-            .await;
+        let poll_request = retry::send(
+            || reqwest_client.get(format!("{}/generate/check/{}", HORDE_API_URL, id)),
+            Idempotency::Idempotent,
+            &retry_notice,
+        )
+        .await;
 
         let poll_response = match poll_request {
             Ok(r) => match r.json::<HordePoll>().await {
@@ -352,38 +478,17 @@ async fn poll_status(
             }
         };
 
-        let status = if poll_response.queue_position > 0.0 {
-            Status::Waiting
-        } else if poll_response.done {
-            Status::Finished
-        } else if poll_response.faulted {
-            return Err(HordeError {
-                message: "An unrecoverable fault has occurred".to_string(),
-            });
-        } else {
-            Status::Processing
-        };
+        let status = poll_response.status()?;
 
         if status != Status::Finished {
+            let Some(status_field) = progress.update(&status, &poll_response) else {
+                continue;
+            };
+
             interaction_client
                 .update_response(interaction_token)
-                .embeds(Some(&[embed::pending("Pending", "")
-                    .field(EmbedFieldBuilder::new("Prompt", prompt))
-                    .field(EmbedFieldBuilder::new("Model", model_name))
-                    .field(EmbedFieldBuilder::new(
-                        "NSFW",
-                        match nsfw {
-                            true => "True",
-                            false => "False",
-                        },
-                    ))
-                    .field(EmbedFieldBuilder::new(
-                        "Status",
-                        format!(
-                            "**{:#?}**\nWait time is {} seconds.\nQueue position is {}.",
-                            status, poll_response.wait_time, poll_response.queue_position
-                        ),
-                    ))
+                .embeds(Some(&[pending_embed("Pending", details)
+                    .field(status_field)
                     .footer(EmbedFooterBuilder::new(id))
                     .build()]))
                 .await
@@ -391,13 +496,12 @@ async fn poll_status(
             continue;
         }
 
-        let final_request = reqwest_client
-            .get(format!(
-                "https://stablehorde.net/api/v2/generate/status/{}",
-                &id
-            ))
-            .send()
-            .await;
+        let final_request = retry::send(
+            || reqwest_client.get(format!("{}/generate/status/{}", HORDE_API_URL, id)),
+            Idempotency::Idempotent,
+            &retry_notice,
+        )
+        .await;
 
         let mut final_response = match final_request {
             Ok(r) => match r.json::<HordeFinal>().await {
@@ -0,0 +1,519 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use twilight_http::client::InteractionClient;
+use twilight_interactions::command::{AutocompleteValue, CommandModel, CreateCommand};
+use twilight_model::application::command::{CommandOptionChoice, CommandOptionChoiceValue};
+use twilight_model::channel::message::Embed;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::embed::{EmbedFieldBuilder, EmbedFooterBuilder};
+
+use super::{job_origin, CommandHandler, CommandHandlerData};
+use crate::ledger::{CallUsage, ProviderCall};
+use crate::storage::{Job, JobKind, JobOrigin, Storage};
+use crate::utils::embed;
+use crate::utils::horde::{
+    models, submit_async, worker_count, HordeError, HordePoll, ModelType, Progress, Status,
+    HORDE_API_URL, HORDE_TIMEOUT_SECS,
+};
+use crate::utils::retry::{self, Idempotency, PendingEmbedNotice};
+
+const MAX_CHOICES: usize = 25;
+const MAX_CHOICE_LENGTH: usize = 100;
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "horde-chat",
+    desc = "Chat with a community LLM on the Stable Horde 👺"
+)]
+pub struct HordeChatCommand {
+    /// Prompt to send to the model.
+    prompt: String,
+    /// Model to use. Any available model is used by default.
+    #[command(autocomplete = true)]
+    model: Option<String>,
+}
+
+#[derive(CommandModel)]
+#[command(autocomplete = true)]
+pub struct HordeChatAutocomplete {
+    model: AutocompleteValue<String>,
+}
+
+// Submit request
+#[derive(Serialize)]
+struct HordeTextParams {
+    max_length: u32,
+    max_context_length: u32,
+}
+
+#[derive(Serialize)]
+struct HordeTextSubmit<'a> {
+    prompt: &'a str,
+    params: HordeTextParams,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    models: Vec<&'a str>,
+    trusted_workers: bool,
+}
+
+// Polling
+#[derive(Deserialize)]
+struct HordeTextStatus {
+    #[serde(flatten)]
+    poll: HordePoll,
+    #[serde(default)]
+    generations: Vec<HordeTextGeneration>,
+}
+
+#[derive(Deserialize)]
+struct HordeTextGeneration {
+    text: String,
+    worker_name: String,
+    model: String,
+}
+
+// Persisted with the job so polling can resume
+#[derive(Serialize, Deserialize)]
+struct HordeChatDetails {
+    prompt: String,
+    model: Option<String>,
+}
+
+#[async_trait]
+impl CommandHandler for HordeChatCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str,
+    ) {
+        let interaction_client = command_handler_data.interaction_client;
+        let reqwest_client = command_handler_data.reqwest_client;
+        let storage = command_handler_data.storage;
+
+        let details = HordeChatDetails {
+            prompt: self.prompt.clone(),
+            model: self.model.clone(),
+        };
+
+        interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        embeds: Some(vec![
+                            embed::prompt(&details.prompt).build(),
+                            embed::pending("Submitting", "")
+                                .field(model_field(&details))
+                                .build(),
+                        ]),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await
+            .ok();
+
+        let call = ProviderCall::start(
+            "horde-chat",
+            "horde",
+            details.model.as_deref().unwrap_or("any"),
+            command_handler_data.user_id.map(Id::get),
+            command_handler_data.guild_id.map(Id::get),
+        );
+        let result = horde_chat(
+            &reqwest_client,
+            &storage,
+            &details,
+            &interaction_client,
+            interaction_token,
+            job_origin(
+                command_handler_data.user_id,
+                command_handler_data.guild_id,
+                interaction_id,
+            ),
+        )
+        .await;
+        call.finish(
+            &storage,
+            CallUsage {
+                success: result.is_ok(),
+                ..Default::default()
+            },
+        );
+
+        if let Err(e) = result {
+            interaction_client
+                .update_response(interaction_token)
+                .embeds(Some(&failure_embeds(&e.message, &details)))
+                .await
+                .ok();
+        }
+    }
+}
+
+impl HordeChatAutocomplete {
+    pub async fn handle_autocomplete(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str,
+    ) {
+        let AutocompleteValue::Focused(query) = &self.model else {
+            return;
+        };
+        let query = query.to_lowercase();
+
+        let models = match models(&command_handler_data.reqwest_client, ModelType::Text).await {
+            Ok(m) => m,
+            Err(e) => {
+                log::warn!("Failed to list Horde text models: {}", e.message);
+                Vec::new()
+            }
+        };
+
+        let choices = models
+            .into_iter()
+            .filter(|m| m.name.len() <= MAX_CHOICE_LENGTH)
+            .filter(|m| m.name.to_lowercase().contains(&query))
+            .take(MAX_CHOICES)
+            .map(|m| {
+                let mut name = format!("{} ({} workers)", m.name, m.count);
+                if name.len() > MAX_CHOICE_LENGTH {
+                    name = m.name.clone();
+                }
+                CommandOptionChoice {
+                    name,
+                    name_localizations: None,
+                    value: CommandOptionChoiceValue::String(m.name),
+                }
+            })
+            .collect();
+
+        command_handler_data
+            .interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+                    data: Some(InteractionResponseData {
+                        choices: Some(choices),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await
+            .ok();
+    }
+}
+
+fn model_field(details: &HordeChatDetails) -> EmbedFieldBuilder {
+    EmbedFieldBuilder::new("Model", details.model.as_deref().unwrap_or("Any"))
+}
+
+fn failure_embeds(message: &str, details: &HordeChatDetails) -> [Embed; 2] {
+    [
+        embed::prompt(&details.prompt).build(),
+        embed::failure(message).field(model_field(details)).build(),
+    ]
+}
+
+/// Picks up a text generation that was still being polled when the bot
+/// stopped, and updates the original interaction once it finishes.
+pub async fn resume_job(
+    reqwest_client: &Client,
+    storage: &Storage,
+    job: Job,
+    interaction_client: &InteractionClient<'_>,
+) {
+    let details: HordeChatDetails = match serde_json::from_str(&job.params) {
+        Ok(d) => d,
+        Err(e) => {
+            log::error!(
+                "Discarding horde-chat job {} with invalid params: {}",
+                job.id,
+                e
+            );
+            storage.remove_job(job.id).ok();
+            return;
+        }
+    };
+
+    log::info!("Resuming Horde text generation {}", job.provider_job_id);
+
+    let call = ProviderCall::start(
+        "horde-chat",
+        "horde",
+        details.model.as_deref().unwrap_or("any"),
+        job.origin.user_id,
+        job.origin.guild_id,
+    );
+    let result = finish_generation(
+        reqwest_client,
+        &job.provider_job_id,
+        &details,
+        Progress::new(
+            UNIX_EPOCH + Duration::from_secs(job.origin.created_at),
+            None,
+        ),
+        interaction_client,
+        &job.interaction_token,
+    )
+    .await;
+
+    call.finish(
+        storage,
+        CallUsage {
+            success: result.is_ok(),
+            ..Default::default()
+        },
+    );
+
+    if let Err(e) = storage.remove_job(job.id) {
+        log::warn!("Failed to remove horde-chat job {}: {}", job.id, e);
+    }
+
+    if let Err(e) = result {
+        interaction_client
+            .update_response(&job.interaction_token)
+            .embeds(Some(&failure_embeds(&e.message, &details)))
+            .await
+            .ok();
+    }
+}
+
+async fn horde_chat(
+    reqwest_client: &Client,
+    storage: &Storage,
+    details: &HordeChatDetails,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+    origin: JobOrigin,
+) -> Result<(), HordeError> {
+    let retry_notice = PendingEmbedNotice {
+        interaction_client,
+        interaction_token,
+        render: |retry_field| {
+            vec![
+                embed::prompt(&details.prompt).build(),
+                embed::pending("Submitting", "")
+                    .field(model_field(details))
+                    .field(retry_field)
+                    .build(),
+            ]
+        },
+    };
+
+    let id = submit_async(
+        reqwest_client,
+        "generate/text/async",
+        &HordeTextSubmit {
+            prompt: &details.prompt,
+            params: HordeTextParams {
+                max_length: 512,
+                max_context_length: 2048,
+            },
+            models: details.model.as_deref().into_iter().collect(),
+            trusted_workers: false,
+        },
+        &retry_notice,
+    )
+    .await?;
+
+    interaction_client
+        .update_response(interaction_token)
+        .embeds(Some(&[
+            embed::prompt(&details.prompt).build(),
+            embed::pending("Submitted", "")
+                .field(model_field(details))
+                .footer(EmbedFooterBuilder::new(&id))
+                .build(),
+        ]))
+        .await
+        .ok();
+
+    let job_params = serde_json::to_string(details).unwrap_or_default();
+
+    let job_id = match storage.insert_job(
+        JobKind::HordeText,
+        interaction_token,
+        origin,
+        &id,
+        &job_params,
+    ) {
+        Ok(job_id) => Some(job_id),
+        Err(e) => {
+            log::warn!("Failed to record horde-chat job {}: {}", id, e);
+            None
+        }
+    };
+
+    let workers = match &details.model {
+        Some(model) => worker_count(reqwest_client, ModelType::Text, &[model]).await,
+        None => None,
+    };
+
+    let result = finish_generation(
+        reqwest_client,
+        &id,
+        details,
+        Progress::new(SystemTime::now(), workers),
+        interaction_client,
+        interaction_token,
+    )
+    .await;
+
+    if let Some(job_id) = job_id {
+        if let Err(e) = storage.remove_job(job_id) {
+            log::warn!("Failed to remove horde-chat job {}: {}", job_id, e);
+        }
+    }
+
+    result
+}
+
+async fn finish_generation(
+    reqwest_client: &Client,
+    id: &str,
+    details: &HordeChatDetails,
+    progress: Progress,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+) -> Result<(), HordeError> {
+    let generation = poll_status(
+        reqwest_client,
+        id,
+        details,
+        progress,
+        interaction_client,
+        interaction_token,
+    )
+    .await?;
+
+    let mut output = generation.text.trim().to_string();
+    if output.is_empty() {
+        return Err(HordeError {
+            message: "The model finished but generated no output.".to_string(),
+        });
+    }
+    if output.len() >= 4096 {
+        output.truncate(output.floor_char_boundary(4092));
+        output += "...";
+    }
+
+    interaction_client
+        .update_response(interaction_token)
+        .embeds(Some(&[
+            embed::prompt(&details.prompt).build(),
+            embed::success()
+                .description(output)
+                .field(EmbedFieldBuilder::new("Model", &generation.model))
+                .field(EmbedFieldBuilder::new(
+                    "Info",
+                    format!(
+                        "Your request was completed by worker `{}`",
+                        generation.worker_name
+                    ),
+                ))
+                .footer(EmbedFooterBuilder::new(id))
+                .build(),
+        ]))
+        .await
+        .ok();
+
+    Ok(())
+}
+
+async fn poll_status(
+    reqwest_client: &Client,
+    id: &str,
+    details: &HordeChatDetails,
+    mut progress: Progress,
+    interaction_client: &InteractionClient<'_>,
+    interaction_token: &str,
+) -> Result<HordeTextGeneration, HordeError> {
+    let retry_notice = PendingEmbedNotice {
+        interaction_client,
+        interaction_token,
+        render: |retry_field| {
+            vec![
+                embed::prompt(&details.prompt).build(),
+                embed::pending("Pending", "")
+                    .field(model_field(details))
+                    .field(retry_field)
+                    .footer(EmbedFooterBuilder::new(id))
+                    .build(),
+            ]
+        },
+    };
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        if progress.elapsed().as_secs() > HORDE_TIMEOUT_SECS {
+            return Err(HordeError {
+                message: "The command timed out after 3 minutes".to_string(),
+            });
+        }
+
+        let poll_request = retry::send(
+            || reqwest_client.get(format!("{}/generate/text/status/{}", HORDE_API_URL, id)),
+            Idempotency::Idempotent,
+            &retry_notice,
+        )
+        .await;
+
+        let mut poll_response = match poll_request {
+            Ok(r) => match r.json::<HordeTextStatus>().await {
+                Ok(j) => j,
+                Err(e) => {
+                    log::warn!("Failed to parse text generation status: {:#?}", e);
+                    continue;
+                }
+            },
+            Err(e) => {
+                return Err(HordeError {
+                    message: format!("{:#?}", e),
+                })
+            }
+        };
+
+        let status = poll_response.poll.status()?;
+
+        if status != Status::Finished {
+            let Some(status_field) = progress.update(&status, &poll_response.poll) else {
+                continue;
+            };
+
+            interaction_client
+                .update_response(interaction_token)
+                .embeds(Some(&[
+                    embed::prompt(&details.prompt).build(),
+                    embed::pending("Pending", "")
+                        .field(model_field(details))
+                        .field(status_field)
+                        .footer(EmbedFooterBuilder::new(id))
+                        .build(),
+                ]))
+                .await
+                .ok();
+            continue;
+        }
+
+        return match poll_response.generations.pop() {
+            Some(g) => Ok(g),
+            None => Err(HordeError {
+                message: "The list of generations was empty".to_string(),
+            }),
+        };
+    }
+}
@@ -0,0 +1,94 @@
+use async_trait::async_trait;
+use twilight_interactions::command::{CommandModel, CreateCommand};
+use twilight_model::channel::message::MessageFlags;
+use twilight_model::guild::Permissions;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::embed::{EmbedBuilder, EmbedFieldBuilder};
+
+use super::{is_bot_owner, CommandHandler, CommandHandlerData};
+use crate::utils::embed;
+use crate::utils::google_ai::{format_duration, key_health};
+
+const MAX_LAST_ERROR_LENGTH: usize = 300;
+
+fn administrator() -> Permissions {
+    Permissions::ADMINISTRATOR
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "keys",
+    desc = "Show the health of the Google API keys",
+    default_permissions = "administrator",
+    dm_permission = false
+)]
+pub struct KeysCommand {}
+
+#[async_trait]
+impl CommandHandler for KeysCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str,
+    ) {
+        // The key pool is shared by every server, so only the owner sees it
+        let keys_embed = match is_bot_owner(command_handler_data.user_id) {
+            true => health_embed(),
+            false => embed::failure("Only the bot owner can view the API keys."),
+        };
+
+        command_handler_data
+            .interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        embeds: Some(vec![keys_embed.build()]),
+                        flags: Some(MessageFlags::EPHEMERAL),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await
+            .ok();
+    }
+}
+
+fn health_embed() -> EmbedBuilder {
+    let health = key_health();
+
+    let mut keys_embed = embed::info().title("Google API keys");
+    if health.is_empty() {
+        keys_embed = keys_embed.description("No keys are configured.");
+    }
+    for key in health {
+        let mut lines = vec![match key.exhausted_for {
+            Some(duration) => format!("Out of quota, resets in {}", format_duration(duration)),
+            None => "Available".to_string(),
+        }];
+        lines.push(format!(
+            "{} succeeded, {} failed",
+            key.successes, key.failures
+        ));
+        if let Some(mut last_error) = key.last_error {
+            if last_error.len() > MAX_LAST_ERROR_LENGTH {
+                last_error.truncate(last_error.floor_char_boundary(MAX_LAST_ERROR_LENGTH));
+                last_error.push_str("...");
+            }
+            lines.push(format!("```\n{}\n```", last_error));
+        }
+
+        keys_embed = keys_embed.field(EmbedFieldBuilder::new(
+            format!("{} (…{})", key.label, key.suffix),
+            lines.join("\n"),
+        ));
+    }
+    keys_embed
+}
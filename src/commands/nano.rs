@@ -1,44 +1,184 @@
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use std::{env, fmt};
 
 use async_trait::async_trait;
 use base64::{engine::general_purpose, DecodeError, Engine as _};
-use image::{DynamicImage, GenericImageView, ImageError, ImageFormat};
+use image::{DynamicImage, GenericImageView, ImageError, ImageFormat, Rgb};
 use log::{error, info};
+use once_cell::sync::Lazy;
 use reqwest::Client;
-use serde::Deserialize;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
 use twilight_http::client::InteractionClient;
 use twilight_http::error::Error as TwilightHttpError;
 use twilight_http::response::DeserializeBodyError;
-use twilight_interactions::command::{CommandModel, CreateCommand};
+use twilight_interactions::command::{CommandModel, CommandOption, CreateCommand, CreateOption};
+use twilight_model::application::command::{Command, CommandType};
+use twilight_model::application::interaction::application_command::CommandData;
+use twilight_model::application::interaction::modal::ModalInteractionData;
+use twilight_model::channel::message::component::{
+    ActionRow, Button, ButtonStyle, Component, TextInput, TextInputStyle,
+};
+use twilight_model::channel::message::{Embed, MessageFlags};
 use twilight_model::channel::Attachment as ChannelAttachment;
 use twilight_model::http::attachment::Attachment as HttpAttachment;
-use twilight_model::http::interaction::{InteractionResponse, InteractionResponseType};
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
 use twilight_model::id::marker::{InteractionMarker, MessageMarker};
 use twilight_model::id::Id;
+use twilight_util::builder::command::CommandBuilder;
 use twilight_util::builder::embed::{
     EmbedBuilder, EmbedFieldBuilder, EmbedFooterBuilder, ImageSource,
 };
 use twilight_validate::message::MessageValidationError;
 
 use crate::activity::get_random_qoute;
+use crate::ledger::{CallUsage, ProviderCall};
+use crate::quota::{self, Provider};
+use crate::storage::{NanoTurn, Storage, StorageError};
 use crate::utils::embed;
 use crate::utils::google_ai::{
-    post_generative_ai, GoogleAiError, GOOGLE_API_FREE_KEY, GOOGLE_API_PAID_KEY,
+    Candidate, Content, CountTokensRequest, GenerateContentRequest, GenerationConfig,
+    GoogleAiClient, GoogleAiError, ImageConfig, Part, SafetyRating, SafetySetting, UsageMetadata,
+    GOOGLE_API_FREE_KEY, GOOGLE_API_PAID_KEY,
+};
+use crate::utils::preprocess::{
+    decode_animation, prepare, preprocess, AlphaHandling, AnimationError, ImagePolicy,
+    OutputFormat, PreparedImage,
 };
+use crate::utils::retry::{RetryNotice, RetryObserver};
 
-use super::{CommandHandler, CommandHandlerData};
+use super::{CommandHandler, CommandHandlerData, INTERACTION_TOKEN_LIFETIME};
 
 const MAX_ERROR_LENGTH: usize = 1000;
+const CONTINUE_ID_PREFIX: &str = "nano:continue:";
+const EDIT_ID_PREFIX: &str = "nano:edit:";
+pub const EDIT_COMMAND_NAME: &str = "Edit with Nano";
+const PROMPT_INPUT_ID: &str = "prompt";
+const DEFAULT_MAX_HISTORY_TURNS: usize = 6;
+const MAX_INPUT_IMAGES: usize = 6;
+// Frames taken from each animated input with FrameSelection::Spread
+const SPREAD_FRAMES: usize = 4;
+// Inline data in a Gemini request is limited to 20 MB
+const MAX_ATTACHMENT_SIZE: u64 = 20 * 1024 * 1024;
+
+const DEFAULT_IMAGE_POLICY: ImagePolicy = ImagePolicy {
+    max_edge: 1024,
+    format: OutputFormat::Png,
+    quality: 90,
+    fix_orientation: true,
+    alpha: AlphaHandling::Keep,
+};
+
+/// Input preprocessing per model, matched by model name prefix.
+const IMAGE_POLICIES: &[(&str, ImagePolicy)] = &[
+    (
+        "gemini-3-pro-image",
+        ImagePolicy {
+            max_edge: 2048,
+            format: OutputFormat::Png,
+            quality: 95,
+            fix_orientation: true,
+            alpha: AlphaHandling::Keep,
+        },
+    ),
+    (
+        "gemini-2.5-flash-image",
+        ImagePolicy {
+            max_edge: 1024,
+            format: OutputFormat::Jpeg,
+            quality: 92,
+            fix_orientation: true,
+            alpha: AlphaHandling::Flatten(Rgb([255, 255, 255])),
+        },
+    ),
+];
+
+/// The policy for `model_name`, with any of `NANO_IMAGE_MAX_EDGE`,
+/// `NANO_IMAGE_FORMAT` and `NANO_IMAGE_QUALITY` taking precedence.
+fn image_policy(model_name: &str) -> ImagePolicy {
+    let mut policy = IMAGE_POLICIES
+        .iter()
+        .find(|(prefix, _)| model_name.starts_with(prefix))
+        .map(|(_, policy)| *policy)
+        .unwrap_or(DEFAULT_IMAGE_POLICY);
+
+    if let Some(max_edge) = env::var("NANO_IMAGE_MAX_EDGE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        policy.max_edge = max_edge;
+    }
+    if let Some(format) = env::var("NANO_IMAGE_FORMAT")
+        .ok()
+        .and_then(|v| OutputFormat::parse(&v))
+    {
+        policy.format = format;
+    }
+    if let Some(quality) = env::var("NANO_IMAGE_QUALITY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        policy.quality = quality;
+    }
+    policy
+}
+const PREVIEW_TILE_SIZE: u32 = 512;
 
 struct NanoError {
     message: String,
 }
 
+// Discord's limits for a single message
+const MAX_EMBEDS: usize = 10;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_TOTAL_EMBED_LENGTH: usize = 6000;
+// Edit the followup with streamed text at most this often
+const PREVIEW_UPDATE_INTERVAL: Duration = Duration::from_millis(750);
+
+enum OutputPart {
+    Text(String),
+    Image(Vec<u8>),
+}
+
+/// Every part of the finished candidates, in the order Gemini returned them.
 struct NanoOutput {
-    text: Option<String>,
-    image: Option<Vec<u8>>,
+    parts: Vec<OutputPart>,
+    usage: Option<UsageMetadata>,
+}
+
+impl NanoOutput {
+    fn text(&self) -> Option<String> {
+        let texts: Vec<&str> = self
+            .parts
+            .iter()
+            .filter_map(|p| match p {
+                OutputPart::Text(text) => Some(text.as_str()),
+                OutputPart::Image(_) => None,
+            })
+            .collect();
+        (!texts.is_empty()).then(|| texts.join("\n\n"))
+    }
+
+    fn image_count(&self) -> usize {
+        self.parts
+            .iter()
+            .filter(|p| matches!(p, OutputPart::Image(_)))
+            .count()
+    }
+
+    fn images(&self) -> Vec<Vec<u8>> {
+        self.parts
+            .iter()
+            .filter_map(|p| match p {
+                OutputPart::Image(image) => Some(image.clone()),
+                OutputPart::Text(_) => None,
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -50,6 +190,10 @@ enum Error {
     Validation(MessageValidationError),
     DeserializeBody(DeserializeBodyError),
     Json(serde_json::Error),
+    Storage(StorageError),
+    SessionExpired,
+    EditExpired,
+    InvalidAttachment(String),
 }
 
 impl fmt::Display for Error {
@@ -62,6 +206,17 @@ impl fmt::Display for Error {
             Self::Validation(e) => write!(f, "Discord message validation error: {}", e),
             Self::DeserializeBody(e) => write!(f, "Failed to process Discord response: {}", e),
             Self::Json(e) => write!(f, "JSON parsing failed: {}", e),
+            Self::Storage(e) => write!(f, "Storage error: {}", e),
+            Self::SessionExpired => write!(
+                f,
+                "This editing session has expired. Start a new one with /nano."
+            ),
+            Self::InvalidAttachment(message) => write!(f, "{}", message),
+            Self::EditExpired => write!(
+                f,
+                "This edit has expired. Use \"{}\" on the message again.",
+                EDIT_COMMAND_NAME
+            ),
         }
     }
 }
@@ -72,6 +227,15 @@ impl From<MessageValidationError> for Error {
     }
 }
 
+impl From<AnimationError> for Error {
+    fn from(e: AnimationError) -> Self {
+        match e {
+            AnimationError::Image(e) => Error::Image(e),
+            AnimationError::TooLarge => Error::InvalidAttachment(e.to_string()),
+        }
+    }
+}
+
 impl From<DeserializeBodyError> for Error {
     fn from(e: DeserializeBodyError) -> Self {
         Error::DeserializeBody(e)
@@ -93,16 +257,144 @@ from_error!(ImageError, Image);
 from_error!(DecodeError, Base64);
 from_error!(TwilightHttpError, DiscordApi);
 from_error!(serde_json::Error, Json);
+from_error!(StorageError, Storage);
+
+#[derive(CommandOption, CreateOption)]
+enum AspectRatio {
+    #[option(name = "Square (1:1)", value = "1:1")]
+    Square,
+    #[option(name = "Portrait (2:3)", value = "2:3")]
+    Portrait23,
+    #[option(name = "Landscape (3:2)", value = "3:2")]
+    Landscape32,
+    #[option(name = "Portrait (3:4)", value = "3:4")]
+    Portrait34,
+    #[option(name = "Landscape (4:3)", value = "4:3")]
+    Landscape43,
+    #[option(name = "Portrait (4:5)", value = "4:5")]
+    Portrait45,
+    #[option(name = "Landscape (5:4)", value = "5:4")]
+    Landscape54,
+    #[option(name = "Tall (9:16)", value = "9:16")]
+    Tall,
+    #[option(name = "Wide (16:9)", value = "16:9")]
+    Wide,
+    #[option(name = "Ultrawide (21:9)", value = "21:9")]
+    Ultrawide,
+}
+
+#[derive(CommandOption, CreateOption, Clone, Copy, Default)]
+enum FrameSelection {
+    #[default]
+    #[option(name = "First frame", value = "first")]
+    First,
+    #[option(name = "Middle frame", value = "middle")]
+    Middle,
+    #[option(name = "Last frame", value = "last")]
+    Last,
+    #[option(name = "Several frames, as separate images", value = "spread")]
+    Spread,
+}
+
+impl FrameSelection {
+    /// Indices of the frames to use out of `count`, which is at least two.
+    fn pick(&self, count: usize) -> Vec<usize> {
+        match self {
+            FrameSelection::First => vec![0],
+            FrameSelection::Middle => vec![count / 2],
+            FrameSelection::Last => vec![count - 1],
+            FrameSelection::Spread => {
+                let picked = SPREAD_FRAMES.min(count);
+                (0..picked)
+                    .map(|i| i * (count - 1) / (picked - 1))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(CommandOption, CreateOption)]
+enum OutputMode {
+    #[option(name = "Text and image", value = "text_image")]
+    TextAndImage,
+    #[option(name = "Image only", value = "image")]
+    ImageOnly,
+}
 
 #[derive(CommandModel, CreateCommand)]
 #[command(name = "nano", desc = "Create an image with Gemini 2.5 Flash (🍌)")]
 pub struct NanoCommand {
     /// Prompt for the model to generate.
     prompt: String,
-    /// Optional main image to use as input.
-    main_image: Option<ChannelAttachment>,
-    /// Optional secondary image to use as input.
-    secondary_image: Option<ChannelAttachment>,
+    /// Optional image to use as input.
+    image_1: Option<ChannelAttachment>,
+    /// Optional image to use as input.
+    image_2: Option<ChannelAttachment>,
+    /// Optional image to use as input.
+    image_3: Option<ChannelAttachment>,
+    /// Optional image to use as input.
+    image_4: Option<ChannelAttachment>,
+    /// Optional image to use as input.
+    image_5: Option<ChannelAttachment>,
+    /// Optional image to use as input.
+    image_6: Option<ChannelAttachment>,
+    /// Shape of the generated image.
+    aspect_ratio: Option<AspectRatio>,
+    /// Whether the model may respond with text alongside the image.
+    output: Option<OutputMode>,
+    /// Sampling temperature, higher is more varied.
+    #[command(min_value = 0.0, max_value = 2.0)]
+    temperature: Option<f64>,
+    /// Number of alternative responses to generate.
+    #[command(min_value = 1, max_value = 4)]
+    candidates: Option<i64>,
+    /// Which frames of animated images to use. Defaults to the first frame.
+    frames: Option<FrameSelection>,
+}
+
+/// Output options for a generation, kept with the session so continued
+/// edits use the same settings.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct NanoConfig {
+    aspect_ratio: Option<String>,
+    image_only: bool,
+    temperature: Option<f64>,
+    candidate_count: Option<i64>,
+}
+
+impl NanoConfig {
+    fn generation_config(&self) -> GenerationConfig {
+        let modalities = match self.image_only {
+            true => vec!["IMAGE"],
+            false => vec!["TEXT", "IMAGE"],
+        };
+        GenerationConfig {
+            response_modalities: Some(modalities.into_iter().map(String::from).collect()),
+            temperature: self.temperature,
+            candidate_count: self.candidate_count,
+            image_config: self.aspect_ratio.as_ref().map(|aspect_ratio| ImageConfig {
+                aspect_ratio: Some(aspect_ratio.clone()),
+            }),
+        }
+    }
+
+    /// Summary for the success footer, leaving out anything left at its default.
+    fn summary(&self) -> Vec<String> {
+        let mut summary = Vec::new();
+        if let Some(aspect_ratio) = &self.aspect_ratio {
+            summary.push(aspect_ratio.clone());
+        }
+        if self.image_only {
+            summary.push("Image only".to_string());
+        }
+        if let Some(temperature) = self.temperature {
+            summary.push(format!("Temp {}", temperature));
+        }
+        if let Some(candidate_count) = self.candidate_count {
+            summary.push(format!("{} candidates", candidate_count));
+        }
+        summary
+    }
 }
 
 #[async_trait]
@@ -113,15 +405,19 @@ impl CommandHandler for NanoCommand {
         interaction_id: Id<InteractionMarker>,
         interaction_token: &'_ str,
     ) {
-        let client = handler_data.interaction_client;
-        let reqwest_client = handler_data.reqwest_client;
         info!("'nano' command received.");
         if let Err(e) = self
-            .run_command(&client, reqwest_client, interaction_id, interaction_token)
+            .run_command(&handler_data, interaction_id, interaction_token)
             .await
         {
             error!("Error executing 'nano' command: {}", e);
-            send_error_message(&client, interaction_token, None, &e.to_string()).await;
+            send_error_message(
+                &handler_data.interaction_client,
+                interaction_token,
+                None,
+                &e.to_string(),
+            )
+            .await;
         }
     }
 }
@@ -129,12 +425,12 @@ impl CommandHandler for NanoCommand {
 impl NanoCommand {
     async fn run_command(
         &self,
-        client: &InteractionClient<'_>,
-        reqwest_client: Client,
+        handler_data: &CommandHandlerData<'_>,
         interaction_id: Id<InteractionMarker>,
         interaction_token: &'_ str,
     ) -> Result<(), Error> {
-        client
+        handler_data
+            .interaction_client
             .create_response(
                 interaction_id,
                 interaction_token,
@@ -146,129 +442,691 @@ impl NanoCommand {
             .await?;
         info!("Interaction deferred.");
 
-        let resized_main = download_and_resize(&reqwest_client, self.main_image.as_ref()).await?;
-        let resized_secondary =
-            download_and_resize(&reqwest_client, self.secondary_image.as_ref()).await?;
+        let attachments: Vec<&ChannelAttachment> = [
+            &self.image_1,
+            &self.image_2,
+            &self.image_3,
+            &self.image_4,
+            &self.image_5,
+            &self.image_6,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        for attachment in &attachments {
+            check_attachment(attachment).map_err(Error::InvalidAttachment)?;
+        }
+        let inputs = NanoInputs {
+            urls: attachments.iter().map(|a| a.url.as_str()).collect(),
+            frames: self.frames.unwrap_or_default(),
+        };
+
+        let config = NanoConfig {
+            aspect_ratio: self.aspect_ratio.as_ref().map(|a| a.value().to_string()),
+            image_only: matches!(self.output, Some(OutputMode::ImageOnly)),
+            temperature: self.temperature,
+            candidate_count: self.candidates,
+        };
 
-        let (prompt_embed, prompt_attachment) = build_prompt_display(
+        generate_from_urls(
+            handler_data,
             &self.prompt,
-            resized_main.as_ref(),
-            resized_secondary.as_ref(),
-        )?;
+            inputs,
+            config,
+            interaction_token,
+        )
+        .await
+    }
+}
 
-        let embeds = [prompt_embed.build()];
-        if let Some(attachment) = prompt_attachment {
-            let attachments = [attachment];
-            client
-                .update_response(interaction_token)
-                .embeds(Some(&embeds))
-                .attachments(&attachments)
-                .await?;
-        } else {
-            client
-                .update_response(interaction_token)
-                .embeds(Some(&embeds))
-                .await?;
+/// Where the input images of a new generation come from.
+struct NanoInputs<'a> {
+    urls: Vec<&'a str>,
+    frames: FrameSelection,
+}
+
+/// The images taken from one downloaded input.
+struct DownloadedInput {
+    images: Vec<PreparedImage>,
+    /// Frame count and the frames used, if the input was animated.
+    animation: Option<(usize, Vec<usize>)>,
+}
+
+/// Downloads the input images, shows them with the prompt and starts a new
+/// editing session. The interaction must already be deferred.
+async fn generate_from_urls(
+    handler_data: &CommandHandlerData<'_>,
+    prompt: &str,
+    inputs: NanoInputs<'_>,
+    config: NanoConfig,
+    interaction_token: &str,
+) -> Result<(), Error> {
+    let client = &handler_data.interaction_client;
+    let reqwest_client = &handler_data.reqwest_client;
+    let model_name = env::var("GEMINI_MODEL").unwrap();
+    let policy = image_policy(&model_name);
+
+    let downloaded = futures::future::try_join_all(
+        inputs
+            .urls
+            .iter()
+            .map(|url| download_and_prepare(reqwest_client, url, &policy, inputs.frames)),
+    )
+    .await?;
+
+    let mut notes = Vec::new();
+    for (i, input) in downloaded.iter().enumerate() {
+        if let Some((count, picked)) = &input.animation {
+            let picked: Vec<String> = picked.iter().map(|f| (f + 1).to_string()).collect();
+            notes.push(format!(
+                "Image {} is animated ({} frames), using frame{} {}",
+                i + 1,
+                count,
+                if picked.len() > 1 { "s" } else { "" },
+                picked.join(", ")
+            ));
         }
-        info!("Initial prompt message sent.");
+    }
+
+    let mut prepared: Vec<PreparedImage> = downloaded.into_iter().flat_map(|d| d.images).collect();
+    if prepared.len() > MAX_INPUT_IMAGES {
+        notes.push(format!(
+            "Only the first {} of {} images were used",
+            MAX_INPUT_IMAGES,
+            prepared.len()
+        ));
+        prepared.truncate(MAX_INPUT_IMAGES);
+    }
+
+    let previews: Vec<DynamicImage> = prepared.iter().map(|p| p.image.clone()).collect();
+    let (prompt_embed, prompt_attachment) = build_prompt_display(prompt, &previews, &notes)?;
 
-        let followup_id = create_generating_followup(&client, interaction_token).await?;
+    let embeds = [prompt_embed.build()];
+    if let Some(attachment) = prompt_attachment {
+        let attachments = [attachment];
+        client
+            .update_response(interaction_token)
+            .embeds(Some(&embeds))
+            .attachments(&attachments)
+            .await?;
+    } else {
+        client
+            .update_response(interaction_token)
+            .embeds(Some(&embeds))
+            .await?;
+    }
+    info!("Initial prompt message sent.");
+
+    let images = prepared.into_iter().map(|p| p.data).collect();
+
+    NanoRequest {
+        model_name,
+        turn: NanoTurn {
+            role: "user".to_string(),
+            text: Some(prompt.to_string()),
+            images,
+        },
+        config,
+        session: None,
+    }
+    .run(handler_data, interaction_token)
+    .await
+}
+
+/// A single /nano generation, optionally continuing an editing session.
+struct NanoRequest {
+    model_name: String,
+    /// The new user turn.
+    turn: NanoTurn,
+    config: NanoConfig,
+    /// The session being continued and its most recent turns.
+    session: Option<(i64, Vec<NanoTurn>)>,
+}
+
+impl NanoRequest {
+    fn footer(&self, tier_used: &str, usage: Option<UsageMetadata>) -> String {
+        let mut footer = format!("Model: {} | Tier: {}", self.model_name, tier_used);
+        if let Some(usage) = usage {
+            footer += &format!(
+                " | Tokens: {} in, {} out",
+                usage.prompt_token_count, usage.candidates_token_count
+            );
+        }
+        let summary = self.config.summary();
+        if !summary.is_empty() {
+            footer += &format!(" | {}", summary.join(" · "));
+        }
+        footer
+    }
+
+    /// Generates into a new followup message and records the exchange so it
+    /// can be continued.
+    async fn run(
+        self,
+        handler_data: &CommandHandlerData<'_>,
+        interaction_token: &str,
+    ) -> Result<(), Error> {
+        let client = &handler_data.interaction_client;
+
+        let quota_ticket = match quota::acquire(
+            &handler_data.storage,
+            Provider::Gemini,
+            handler_data.user_id.map(Id::get),
+            handler_data.guild_id.map(Id::get),
+        ) {
+            Ok(ticket) => ticket,
+            Err(exceeded) => {
+                client
+                    .create_followup(interaction_token)
+                    .embeds(&[exceeded.embed()])
+                    .flags(MessageFlags::EPHEMERAL)
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let followup_id = create_generating_followup(client, interaction_token).await?;
         info!(
             "Followup created with ID {}. Calling Gemini API...",
             followup_id
         );
 
-        let model_name = env::var("GEMINI_MODEL").unwrap();
+        let safety_settings = match handler_data.guild_id {
+            Some(guild_id) => handler_data
+                .storage
+                .safety_settings(guild_id.get())
+                .unwrap_or_else(|e| {
+                    error!("Failed to load safety settings: {}", e);
+                    Vec::new()
+                }),
+            None => Vec::new(),
+        };
+
+        let mut preview = LivePreview {
+            client,
+            token: interaction_token,
+            followup_id,
+            last_update: Instant::now(),
+        };
 
+        let call = ProviderCall::start(
+            "nano",
+            "google",
+            &self.model_name,
+            handler_data.user_id.map(Id::get),
+            handler_data.guild_id.map(Id::get),
+        );
         match nano(
-            &reqwest_client,
-            &model_name,
-            &self.prompt,
-            resized_main.as_ref(),
-            resized_secondary.as_ref(),
+            &handler_data.reqwest_client,
+            &self,
+            &safety_settings,
+            &mut preview,
         )
         .await
         {
             Ok((output, tier_used)) => {
+                call.finish(
+                    &handler_data.storage,
+                    CallUsage {
+                        success: true,
+                        tier: Some(tier_used),
+                        images: output.image_count() as u32,
+                        input_tokens: output.usage.map_or(0, |u| u.prompt_token_count),
+                        output_tokens: output.usage.map_or(0, |u| u.candidates_token_count),
+                    },
+                );
                 info!("nano function returned Ok. Preparing final update for followup.");
+                let session_id = match self.record_turns(&handler_data.storage, &output) {
+                    Ok(id) => Some(id),
+                    Err(e) => {
+                        error!("Failed to record nano session: {}", e);
+                        None
+                    }
+                };
+                let footer = self.footer(tier_used, output.usage);
                 send_success_followup(
-                    &client,
+                    client,
                     interaction_token,
                     followup_id,
                     output,
-                    &model_name,
-                    tier_used,
+                    &footer,
+                    session_id,
                 )
                 .await?;
                 info!("Final update sent successfully.");
             }
             Err(e) => {
+                call.finish(&handler_data.storage, CallUsage::failed());
+                quota_ticket.refund(&handler_data.storage);
                 error!("nano function returned an error: {}", e.message);
-                send_error_message(&client, interaction_token, Some(followup_id), &e.message).await;
+                send_error_message(client, interaction_token, Some(followup_id), &e.message).await;
                 info!("Final error update sent successfully.");
             }
         }
 
         Ok(())
     }
+
+    fn record_turns(&self, storage: &Storage, output: &NanoOutput) -> Result<i64, StorageError> {
+        let session_id = match &self.session {
+            Some((id, _)) => *id,
+            None => {
+                let config = serde_json::to_string(&self.config).unwrap_or_default();
+                storage.create_nano_session(&self.model_name, &config)?
+            }
+        };
+
+        storage.append_nano_turns(
+            session_id,
+            &[
+                self.turn.clone(),
+                NanoTurn {
+                    role: "model".to_string(),
+                    text: output.text(),
+                    images: output.images(),
+                },
+            ],
+        )?;
+
+        Ok(session_id)
+    }
+}
+
+/// Opens the prompt modal for a "Continue editing" button.
+pub async fn handle_component(
+    handler_data: CommandHandlerData<'_>,
+    custom_id: &str,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &'_ str,
+) {
+    if parse_session_id(custom_id).is_none() {
+        return;
+    }
+
+    if let Err(e) = handler_data
+        .interaction_client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &prompt_modal(custom_id, "Continue editing", "Now make the sky red"),
+        )
+        .await
+    {
+        error!("Failed to open continue modal: {}", e);
+    }
+}
+
+fn prompt_modal(custom_id: &str, title: &str, placeholder: &str) -> InteractionResponse {
+    let prompt_input = TextInput {
+        custom_id: PROMPT_INPUT_ID.to_string(),
+        label: "What should change?".to_string(),
+        max_length: Some(2000),
+        min_length: Some(1),
+        placeholder: Some(placeholder.to_string()),
+        required: Some(true),
+        style: TextInputStyle::Paragraph,
+        value: None,
+    };
+
+    InteractionResponse {
+        kind: InteractionResponseType::Modal,
+        data: Some(InteractionResponseData {
+            custom_id: Some(custom_id.to_string()),
+            title: Some(title.to_string()),
+            components: Some(vec![Component::ActionRow(ActionRow {
+                components: vec![Component::TextInput(prompt_input)],
+            })]),
+            ..Default::default()
+        }),
+    }
+}
+
+type PendingEdits = HashMap<Id<InteractionMarker>, (Instant, Vec<String>)>;
+
+// Image urls collected by the context menu command, waiting for its modal
+static PENDING_EDITS: Lazy<Mutex<PendingEdits>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The "Edit with Nano" message context menu command.
+pub fn edit_command() -> Command {
+    CommandBuilder::new(EDIT_COMMAND_NAME, "", CommandType::Message).build()
+}
+
+/// Collects the images of the targeted message and asks for the edit prompt.
+pub async fn handle_edit_command(
+    handler_data: CommandHandlerData<'_>,
+    command_data: &CommandData,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &'_ str,
+) {
+    let client = handler_data.interaction_client;
+    info!("'{}' command received.", EDIT_COMMAND_NAME);
+
+    let message = command_data.target_id.and_then(|target_id| {
+        command_data
+            .resolved
+            .as_ref()?
+            .messages
+            .get(&target_id.cast())
+    });
+
+    let mut urls: Vec<String> = Vec::new();
+    if let Some(message) = message {
+        urls.extend(
+            message
+                .attachments
+                .iter()
+                .filter(|a| check_attachment(a).is_ok())
+                .map(|a| a.url.clone()),
+        );
+        urls.extend(message.embeds.iter().filter_map(|e| {
+            e.image
+                .as_ref()
+                .map(|i| i.url.clone())
+                .or_else(|| e.thumbnail.as_ref().map(|t| t.url.clone()))
+        }));
+    }
+    urls.truncate(MAX_INPUT_IMAGES);
+
+    if urls.is_empty() {
+        client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        embeds: Some(vec![
+                            embed::failure("That message has no images to edit.").build()
+                        ]),
+                        flags: Some(MessageFlags::EPHEMERAL),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await
+            .ok();
+        return;
+    }
+
+    {
+        let mut pending = PENDING_EDITS.lock().unwrap();
+        let lifetime = Duration::from_secs(INTERACTION_TOKEN_LIFETIME);
+        pending.retain(|_, (created, _)| created.elapsed() < lifetime);
+        pending.insert(interaction_id, (Instant::now(), urls));
+    }
+
+    let custom_id = format!("{}{}", EDIT_ID_PREFIX, interaction_id);
+    if let Err(e) = client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &prompt_modal(
+                &custom_id,
+                EDIT_COMMAND_NAME,
+                "Make it look like a watercolor",
+            ),
+        )
+        .await
+    {
+        error!("Failed to open edit modal: {}", e);
+    }
+}
+
+/// Runs the prompt submitted through a continue or edit modal.
+pub async fn handle_modal(
+    handler_data: CommandHandlerData<'_>,
+    modal: &ModalInteractionData,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &'_ str,
+) {
+    let prompt = modal
+        .components
+        .iter()
+        .flat_map(|row| &row.components)
+        .find(|c| c.custom_id == PROMPT_INPUT_ID)
+        .and_then(|c| c.value.clone())
+        .unwrap_or_default();
+
+    let result = if let Some(session_id) = parse_session_id(&modal.custom_id) {
+        info!("'nano' continuation received for session {}.", session_id);
+        continue_session(
+            &handler_data,
+            session_id,
+            prompt,
+            interaction_id,
+            interaction_token,
+        )
+        .await
+    } else if let Some(edit_id) = parse_edit_id(&modal.custom_id) {
+        info!("'nano' edit prompt received.");
+        run_edit(
+            &handler_data,
+            edit_id,
+            &prompt,
+            interaction_id,
+            interaction_token,
+        )
+        .await
+    } else {
+        return;
+    };
+
+    if let Err(e) = result {
+        error!("Error executing 'nano' modal: {}", e);
+        send_error_message(
+            &handler_data.interaction_client,
+            interaction_token,
+            None,
+            &e.to_string(),
+        )
+        .await;
+    }
+}
+
+fn parse_edit_id(custom_id: &str) -> Option<Id<InteractionMarker>> {
+    custom_id.strip_prefix(EDIT_ID_PREFIX)?.parse().ok()
+}
+
+async fn run_edit(
+    handler_data: &CommandHandlerData<'_>,
+    edit_id: Id<InteractionMarker>,
+    prompt: &str,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &str,
+) -> Result<(), Error> {
+    handler_data
+        .interaction_client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: None,
+            },
+        )
+        .await?;
+
+    let (_, urls) = PENDING_EDITS
+        .lock()
+        .unwrap()
+        .remove(&edit_id)
+        .ok_or(Error::EditExpired)?;
+    let inputs = NanoInputs {
+        urls: urls.iter().map(String::as_str).collect(),
+        frames: FrameSelection::default(),
+    };
+
+    generate_from_urls(
+        handler_data,
+        prompt,
+        inputs,
+        NanoConfig::default(),
+        interaction_token,
+    )
+    .await
+}
+
+fn parse_session_id(custom_id: &str) -> Option<i64> {
+    custom_id.strip_prefix(CONTINUE_ID_PREFIX)?.parse().ok()
+}
+
+async fn continue_session(
+    handler_data: &CommandHandlerData<'_>,
+    session_id: i64,
+    prompt: String,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &str,
+) -> Result<(), Error> {
+    let client = &handler_data.interaction_client;
+    client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: None,
+            },
+        )
+        .await?;
+
+    let max_turns = env::var("NANO_MAX_HISTORY_TURNS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_HISTORY_TURNS);
+
+    let session = handler_data
+        .storage
+        .nano_session(session_id, max_turns)?
+        .ok_or(Error::SessionExpired)?;
+
+    // Gemini expects the conversation to open with a user turn
+    let mut turns = session.turns;
+    while turns.first().is_some_and(|t| t.role != "user") {
+        turns.remove(0);
+    }
+
+    let prompt_embed = embed::prompt(&prompt)
+        .description("Continuing the previous edit")
+        .build();
+    client
+        .update_response(interaction_token)
+        .embeds(Some(&[prompt_embed]))
+        .await?;
+
+    let turn = NanoTurn {
+        role: "user".to_string(),
+        text: Some(prompt),
+        images: Vec::new(),
+    };
+    fit_history(
+        &handler_data.reqwest_client,
+        &session.model,
+        &mut turns,
+        &turn,
+    )
+    .await;
+
+    NanoRequest {
+        model_name: session.model,
+        turn,
+        config: serde_json::from_str(&session.config).unwrap_or_default(),
+        session: Some((session_id, turns)),
+    }
+    .run(handler_data, interaction_token)
+    .await
+}
+
+/// Rejects attachments that are not images or are too large to send,
+/// before anything is downloaded.
+fn check_attachment(attachment: &ChannelAttachment) -> Result<(), String> {
+    let is_image = attachment
+        .content_type
+        .as_deref()
+        .is_some_and(|t| t.starts_with("image/"));
+    if !is_image {
+        return Err(format!("`{}` is not an image.", attachment.filename));
+    }
+    if attachment.size > MAX_ATTACHMENT_SIZE {
+        return Err(format!(
+            "`{}` is larger than {} MB.",
+            attachment.filename,
+            MAX_ATTACHMENT_SIZE / 1024 / 1024
+        ));
+    }
+    Ok(())
 }
 
-async fn download_and_resize(
+async fn download_and_prepare(
     client: &Client,
-    attachment: Option<&ChannelAttachment>,
-) -> Result<Option<DynamicImage>, Error> {
-    if let Some(att) = attachment {
-        info!("Downloading and resizing image from {}", att.url);
-        let bytes = client.get(&att.url).send().await?.bytes().await?;
-        let image = image::load_from_memory(&bytes)?;
-        return Ok(Some(image.thumbnail(1024, 1024)));
+    url: &str,
+    policy: &ImagePolicy,
+    frames: FrameSelection,
+) -> Result<DownloadedInput, Error> {
+    info!("Downloading and preparing image from {}", url);
+    let bytes = client.get(url).send().await?.bytes().await?;
+
+    let Some(animation) = decode_animation(&bytes, |count| frames.pick(count))? else {
+        return Ok(DownloadedInput {
+            images: vec![preprocess(&bytes, policy)?],
+            animation: None,
+        });
+    };
+
+    let mut images = Vec::new();
+    for frame in animation.frames {
+        images.push(prepare(frame, policy)?);
     }
-    Ok(None)
+
+    Ok(DownloadedInput {
+        images,
+        animation: Some((animation.frame_count, animation.picked)),
+    })
 }
 
-fn concat_images_horizontally(img1: &DynamicImage, img2: &DynamicImage) -> DynamicImage {
-    let (w1, h1) = img1.dimensions();
-    let (w2, h2) = img2.dimensions();
-    let new_height = h1.max(h2);
-    let mut combined = image::RgbaImage::new(w1 + w2, new_height);
-    image::imageops::overlay(&mut combined, img1, 0, 0);
-    image::imageops::overlay(&mut combined, img2, w1 as i64, 0);
-    DynamicImage::ImageRgba8(combined)
+/// Lays the images out in a roughly square grid, each centered in its tile.
+fn tile_images(images: &[DynamicImage]) -> DynamicImage {
+    let columns = (images.len() as f64).sqrt().ceil() as u32;
+    let rows = (images.len() as u32).div_ceil(columns);
+    let mut grid = image::RgbaImage::new(columns * PREVIEW_TILE_SIZE, rows * PREVIEW_TILE_SIZE);
+
+    for (i, image) in images.iter().enumerate() {
+        let tile = image.thumbnail(PREVIEW_TILE_SIZE, PREVIEW_TILE_SIZE);
+        let (width, height) = tile.dimensions();
+        let x = (i as u32 % columns) * PREVIEW_TILE_SIZE + (PREVIEW_TILE_SIZE - width) / 2;
+        let y = (i as u32 / columns) * PREVIEW_TILE_SIZE + (PREVIEW_TILE_SIZE - height) / 2;
+        image::imageops::overlay(&mut grid, &tile, x as i64, y as i64);
+    }
+
+    DynamicImage::ImageRgba8(grid)
 }
 
 fn build_prompt_display(
     prompt: &str,
-    main_img: Option<&DynamicImage>,
-    sec_img: Option<&DynamicImage>,
+    images: &[DynamicImage],
+    notes: &[String],
 ) -> Result<(EmbedBuilder, Option<HttpAttachment>), ImageError> {
     let mut embed = embed::prompt(prompt);
+    if !notes.is_empty() {
+        embed = embed.field(EmbedFieldBuilder::new("Input", notes.join("\n")));
+    }
 
-    let attachment_data = match (main_img, sec_img) {
-        (Some(main), Some(sec)) => {
-            info!("Two images found, creating concatenated prompt image.");
-            let combined = concat_images_horizontally(main, sec);
-            let mut bytes = Cursor::new(Vec::new());
-            combined.write_to(&mut bytes, ImageFormat::Png)?;
-            Some(("prompt_combined.png", bytes.into_inner()))
-        }
-        (Some(main), None) => {
-            info!("One image found, creating prompt image.");
-            let mut bytes = Cursor::new(Vec::new());
-            main.write_to(&mut bytes, ImageFormat::Png)?;
-            Some(("prompt_main.png", bytes.into_inner()))
+    let preview = match images {
+        [] => return Ok((embed, None)),
+        [image] => encode_png(image)?,
+        _ => {
+            info!(
+                "{} images found, creating tiled prompt image.",
+                images.len()
+            );
+            encode_png(&tile_images(images))?
         }
-        _ => None,
     };
 
-    if let Some((filename, bytes)) = attachment_data {
-        embed = embed.image(ImageSource::attachment(filename).unwrap());
-        Ok((
-            embed,
-            Some(HttpAttachment::from_bytes(filename.to_string(), bytes, 1)),
-        ))
-    } else {
-        Ok((embed, None))
-    }
+    let filename = "prompt.png";
+    Ok((
+        embed.image(ImageSource::attachment(filename).unwrap()),
+        Some(HttpAttachment::from_bytes(filename.to_string(), preview, 1)),
+    ))
 }
 
 async fn create_generating_followup(
@@ -290,40 +1148,113 @@ async fn send_success_followup(
     token: &str,
     id: Id<MessageMarker>,
     output: NanoOutput,
-    model_name: &str,
-    tier_used: &str,
+    footer_text: &str,
+    session_id: Option<i64>,
 ) -> Result<(), Error> {
-    let footer_text = format!("Model: {} | Tier: {}", model_name, tier_used);
-    let footer = EmbedFooterBuilder::new(footer_text).build();
-    let mut embed_builder = embed::success().footer(footer);
+    let (embeds, attachments) = render_output(output, footer_text);
+    let components: Vec<Component> = session_id.map(continue_button).into_iter().collect();
+
+    client
+        .update_followup(token, id)
+        .embeds(Some(&embeds))
+        .components(Some(&components))
+        .attachments(&attachments)
+        .await?;
 
-    if let Some(text) = output.text {
-        embed_builder = embed_builder.field(EmbedFieldBuilder::new("Response", text));
+    Ok(())
+}
+
+/// Lays the output out as one embed per image, each carrying the text that
+/// preceded it. Text after the last image gets an embed of its own.
+fn render_output(output: NanoOutput, footer_text: &str) -> (Vec<Embed>, Vec<HttpAttachment>) {
+    let mut embeds: Vec<EmbedBuilder> = Vec::new();
+    let mut attachments = Vec::new();
+    let mut description = String::new();
+    // Title and footer count towards the limit on the message's embeds
+    let mut budget = MAX_TOTAL_EMBED_LENGTH - "Success".len() - footer_text.chars().count();
+
+    let new_embed = |embeds: &[EmbedBuilder]| match embeds.is_empty() {
+        true => embed::success(),
+        false => EmbedBuilder::new().color(embed::SUCCESS_COLOR),
+    };
+
+    for part in output.parts {
+        match part {
+            OutputPart::Text(text) => {
+                if !description.is_empty() {
+                    description += "\n\n";
+                }
+                description += text.trim();
+            }
+            // Any images past the limit are dropped, their text is kept
+            OutputPart::Image(_) if embeds.len() == MAX_EMBEDS - 1 => {}
+            OutputPart::Image(image) => {
+                let filename = format!("image_{}.png", attachments.len() + 1);
+                let mut embed_builder =
+                    new_embed(&embeds).image(ImageSource::attachment(&filename).unwrap());
+                if !description.is_empty() {
+                    embed_builder = embed_builder.description(truncate(&description, &mut budget));
+                    description.clear();
+                }
+                embeds.push(embed_builder);
+                attachments.push(HttpAttachment::from_bytes(
+                    filename,
+                    image,
+                    attachments.len() as u64 + 1,
+                ));
+            }
+        }
     }
 
-    let filename = "image.png".to_string();
-    if output.image.is_some() {
-        embed_builder = embed_builder.image(ImageSource::attachment(&filename).unwrap());
+    if !description.is_empty() || embeds.is_empty() {
+        let embed_builder = new_embed(&embeds);
+        embeds.push(match description.is_empty() {
+            true => embed_builder,
+            false => embed_builder.description(truncate(&description, &mut budget)),
+        });
     }
 
-    let embeds = [embed_builder.build()];
+    let last = embeds.len() - 1;
+    let embeds = embeds
+        .into_iter()
+        .enumerate()
+        .map(|(i, e)| match i == last {
+            true => e.footer(EmbedFooterBuilder::new(footer_text)).build(),
+            false => e.build(),
+        })
+        .collect();
 
-    if let Some(image_bytes) = output.image {
-        let attachment = HttpAttachment::from_bytes(filename, image_bytes, 1);
-        let attachments = [attachment];
-        client
-            .update_followup(token, id)
-            .embeds(Some(&embeds))
-            .attachments(&attachments)
-            .await?;
+    (embeds, attachments)
+}
+
+/// Shortens `text` to fit both a single description and what remains of
+/// `budget`, which is reduced by the returned length.
+fn truncate(text: &str, budget: &mut usize) -> String {
+    let limit = MAX_DESCRIPTION_LENGTH.min(*budget);
+    let length = text.chars().count();
+    let truncated = if length <= limit {
+        text.to_string()
     } else {
-        client
-            .update_followup(token, id)
-            .embeds(Some(&embeds))
-            .await?;
-    }
+        let mut truncated: String = text.chars().take(limit.saturating_sub(3)).collect();
+        truncated += "...";
+        truncated
+    };
+    *budget -= truncated.chars().count().min(*budget);
+    truncated
+}
 
-    Ok(())
+fn continue_button(session_id: i64) -> Component {
+    Component::ActionRow(ActionRow {
+        components: vec![Component::Button(Button {
+            custom_id: Some(format!("{}{}", CONTINUE_ID_PREFIX, session_id)),
+            disabled: false,
+            emoji: None,
+            label: Some("Continue editing".to_string()),
+            style: ButtonStyle::Secondary,
+            url: None,
+            sku_id: None,
+        })],
+    })
 }
 
 async fn send_error_message(
@@ -355,126 +1286,292 @@ async fn send_error_message(
     }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct GeminiResponse {
-    candidates: Option<Vec<Candidate>>,
-    prompt_feedback: Option<PromptFeedback>,
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, ImageError> {
+    let mut buf = Cursor::new(Vec::new());
+    image.write_to(&mut buf, ImageFormat::Png)?;
+    Ok(buf.into_inner())
 }
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Candidate {
-    content: Content,
-    finish_reason: Option<String>,
+
+fn blocked_error(reason: String, message: Option<String>, ratings: &[SafetyRating]) -> NanoError {
+    NanoError {
+        message: GoogleAiError::blocked(reason, message, ratings).message,
+    }
 }
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Content {
-    parts: Vec<Part>,
+
+fn turn_to_content(turn: &NanoTurn) -> Content {
+    let mut parts = Vec::new();
+    let images = turn.images.iter().map(|image| {
+        let mime_type = image::guess_format(image)
+            .map(|format| format.to_mime_type())
+            .unwrap_or("image/png");
+        Part::inline_data(mime_type, general_purpose::STANDARD.encode(image))
+    });
+    let text = turn.text.as_deref().map(Part::text);
+
+    // Inputs lead with the images, like a fresh request; outputs lead with text
+    if turn.role == "user" {
+        parts.extend(images);
+        parts.extend(text);
+    } else {
+        parts.extend(text);
+        parts.extend(images);
+    }
+
+    Content {
+        role: Some(turn.role.clone()),
+        parts,
+    }
 }
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Part {
-    text: Option<String>,
-    inline_data: Option<InlineData>,
+
+/// Input token limits by model, listed once per process.
+static INPUT_TOKEN_LIMITS: Lazy<tokio::sync::Mutex<HashMap<String, u64>>> =
+    Lazy::new(|| tokio::sync::Mutex::new(HashMap::new()));
+
+async fn input_token_limit(google_ai: &GoogleAiClient<'_>, model_name: &str) -> Option<u64> {
+    let mut limits = INPUT_TOKEN_LIMITS.lock().await;
+    if limits.is_empty() {
+        let models = google_ai
+            .list_models()
+            .await
+            .map_err(|e| error!("Failed to list Gemini models: {}", e))
+            .ok()?;
+        limits.extend(models.body.into_iter().filter_map(|model| {
+            let name = model.name.trim_start_matches("models/").to_string();
+            Some((name, model.input_token_limit?))
+        }));
+    }
+    limits.get(model_name).copied()
 }
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct InlineData {
-    data: String,
+
+/// Drops the oldest exchanges of a continued session until they and the new
+/// turn fit in the model's context window.
+async fn fit_history(
+    reqwest_client: &Client,
+    model_name: &str,
+    turns: &mut Vec<NanoTurn>,
+    turn: &NanoTurn,
+) {
+    let google_ai =
+        GoogleAiClient::new(reqwest_client, &[GOOGLE_API_FREE_KEY, GOOGLE_API_PAID_KEY]);
+    let Some(limit) = input_token_limit(&google_ai, model_name).await else {
+        return;
+    };
+
+    while !turns.is_empty() {
+        let request = CountTokensRequest {
+            contents: turns.iter().chain([turn]).map(turn_to_content).collect(),
+        };
+        let total_tokens = match google_ai.count_tokens(model_name, &request).await {
+            Ok(response) => response.body.total_tokens,
+            Err(e) => {
+                error!("Failed to count tokens: {}", e);
+                return;
+            }
+        };
+        if total_tokens <= limit {
+            return;
+        }
+
+        info!(
+            "Request has {} tokens, over the limit of {}. Dropping the oldest turns.",
+            total_tokens, limit
+        );
+        // Drop a user turn together with the model's reply to it, so the
+        // history still opens with a user turn
+        turns.remove(0);
+        while turns.first().is_some_and(|t| t.role != "user") {
+            turns.remove(0);
+        }
+    }
 }
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct PromptFeedback {
-    block_reason: Option<String>,
+
+/// Shows the text streamed so far in the "Generating..." followup, and any
+/// retries before the stream opens.
+struct LivePreview<'a> {
+    client: &'a InteractionClient<'a>,
+    token: &'a str,
+    followup_id: Id<MessageMarker>,
+    last_update: Instant,
 }
 
-fn image_to_json_part(image: &DynamicImage) -> Result<serde_json::Value, ImageError> {
-    let mut buf = Cursor::new(Vec::new());
-    image.write_to(&mut buf, ImageFormat::Png)?;
-    let data = general_purpose::STANDARD.encode(buf.into_inner());
-    Ok(json!({ "inline_data": { "mime_type": "image/png", "data": data } }))
+impl LivePreview<'_> {
+    async fn update(&mut self, text: &str) {
+        if text.trim().is_empty() || self.last_update.elapsed() < PREVIEW_UPDATE_INTERVAL {
+            return;
+        }
+        self.last_update = Instant::now();
+
+        let mut budget = MAX_DESCRIPTION_LENGTH;
+        let embed = embed::pending("Generating...", &truncate(text.trim(), &mut budget)).build();
+        self.client
+            .update_followup(self.token, self.followup_id)
+            .embeds(Some(&[embed]))
+            .await
+            .ok();
+    }
+}
+
+#[async_trait]
+impl RetryObserver for LivePreview<'_> {
+    async fn retrying(&self, notice: &RetryNotice) {
+        let embed = embed::pending("Generating...", "")
+            .field(notice.field())
+            .build();
+        self.client
+            .update_followup(self.token, self.followup_id)
+            .embeds(Some(&[embed]))
+            .await
+            .ok();
+    }
+}
+
+/// Folds a streamed chunk into the candidates received so far.
+fn merge_chunk(candidates: &mut Vec<Candidate>, chunk: Vec<Candidate>) {
+    for chunk_candidate in chunk {
+        let Some(candidate) = candidates
+            .iter_mut()
+            .find(|c| c.index == chunk_candidate.index)
+        else {
+            candidates.push(chunk_candidate);
+            continue;
+        };
+
+        if let Some(content) = chunk_candidate.content {
+            match &mut candidate.content {
+                Some(existing) => existing.parts.extend(content.parts),
+                None => candidate.content = Some(content),
+            }
+        }
+        if chunk_candidate.finish_reason.is_some() {
+            candidate.finish_reason = chunk_candidate.finish_reason;
+        }
+        if chunk_candidate.finish_message.is_some() {
+            candidate.finish_message = chunk_candidate.finish_message;
+        }
+        if chunk_candidate.safety_ratings.is_some() {
+            candidate.safety_ratings = chunk_candidate.safety_ratings;
+        }
+    }
+}
+
+fn streamed_text(candidates: &[Candidate]) -> String {
+    candidates
+        .iter()
+        .filter_map(|c| c.content.as_ref())
+        .flat_map(|c| &c.parts)
+        .filter_map(|p| p.text.as_deref())
+        .collect()
 }
 
 async fn nano(
     reqwest_client: &Client,
-    model_name: &str,
-    prompt: &str,
-    main_image: Option<&DynamicImage>,
-    secondary_image: Option<&DynamicImage>,
+    request: &NanoRequest,
+    safety_settings: &[(String, String)],
+    preview: &mut LivePreview<'_>,
 ) -> Result<(NanoOutput, &'static str), NanoError> {
-    let mut parts: Vec<serde_json::Value> = Vec::new();
+    let google_ai =
+        GoogleAiClient::new(reqwest_client, &[GOOGLE_API_FREE_KEY, GOOGLE_API_PAID_KEY])
+            .with_retry_observer(&*preview);
 
-    if let Some(image) = secondary_image {
-        parts.push(image_to_json_part(image).map_err(|e| NanoError {
-            message: format!("Failed to encode secondary image: {}", e),
-        })?);
-    }
-    if let Some(image) = main_image {
-        parts.push(image_to_json_part(image).map_err(|e| NanoError {
-            message: format!("Failed to encode main image: {}", e),
-        })?);
+    let mut contents: Vec<Content> = match &request.session {
+        Some((_, turns)) => turns.iter().map(turn_to_content).collect(),
+        None => Vec::new(),
+    };
+    contents.push(turn_to_content(&request.turn));
+
+    let request_body = GenerateContentRequest {
+        contents,
+        generation_config: Some(request.config.generation_config()),
+        safety_settings: safety_settings
+            .iter()
+            .map(|(category, threshold)| SafetySetting {
+                category: category.clone(),
+                threshold: threshold.clone(),
+            })
+            .collect(),
+    };
+
+    let mut stream = google_ai
+        .stream_generate_content(&request.model_name, &request_body)
+        .await
+        .map_err(|e| NanoError { message: e.message })?;
+    let tier_used = stream.tier_used;
+
+    let mut candidates = Vec::new();
+    let mut prompt_feedback = None;
+    let mut usage = None;
+    while let Some(chunk) = stream.next_chunk().await {
+        let gemini_response = chunk.map_err(|e| NanoError { message: e.message })?;
+
+        if gemini_response.prompt_feedback.is_some() {
+            prompt_feedback = gemini_response.prompt_feedback;
+        }
+        // Each chunk reports the running totals
+        if gemini_response.usage_metadata.is_some() {
+            usage = gemini_response.usage_metadata;
+        }
+        merge_chunk(&mut candidates, gemini_response.candidates);
+        preview.update(&streamed_text(&candidates)).await;
     }
-    parts.push(json!({ "text": prompt }));
 
-    let request_body = json!({ "contents": [{ "parts": parts }] });
-    let api_url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent",
-        model_name
-    );
+    if let Some(feedback) = prompt_feedback {
+        if let Some(reason) = feedback.block_reason {
+            return Err(blocked_error(
+                format!("Request blocked by safety filter: {}", reason),
+                feedback.block_reason_message,
+                &feedback.safety_ratings.unwrap_or_default(),
+            ));
+        }
+    }
 
-    let keys_to_try = [GOOGLE_API_FREE_KEY, GOOGLE_API_PAID_KEY];
-    let google_ai_response =
-        post_generative_ai(reqwest_client, &api_url, &request_body, &keys_to_try)
-            .await
-            .map_err(|e: GoogleAiError| NanoError { message: e.message })?;
-    let text = google_ai_response.text;
-    let tier_used = google_ai_response.tier_used;
-
-    let gemini_response: GeminiResponse = serde_json::from_str(&text).map_err(|e| NanoError {
-        message: format!(
-            "JSON Parse Error with tier {}: {}
-Response: {}",
-            tier_used, e, text
-        ),
-    })?;
-
-    if let Some(reason) = gemini_response
-        .prompt_feedback
-        .and_then(|fb| fb.block_reason)
-    {
-        return Err(NanoError {
-            message: format!("Request blocked by safety filter: {}", reason),
+    let (candidates, stopped): (Vec<Candidate>, Vec<Candidate>) = candidates
+        .into_iter()
+        .partition(|c| c.finish_reason.as_deref() == Some("STOP"));
+    if candidates.is_empty() {
+        // Report why the first candidate stopped, e.g. SAFETY or IMAGE_SAFETY
+        return Err(match stopped.into_iter().next() {
+            Some(candidate) => blocked_error(
+                format!(
+                    "Generation stopped: {}",
+                    candidate.finish_reason.as_deref().unwrap_or("UNKNOWN")
+                ),
+                candidate.finish_message,
+                &candidate.safety_ratings.unwrap_or_default(),
+            ),
+            None => NanoError {
+                message: "No candidates in response.".to_string(),
+            },
         });
     }
 
-    let candidate = gemini_response
-        .candidates
-        .unwrap_or_default()
+    let mut parts = Vec::new();
+    for part in candidates
         .into_iter()
-        .find(|c| c.finish_reason.as_deref() == Some("STOP"))
-        .ok_or_else(|| NanoError {
-            message: "No valid candidates in response.".to_string(),
-        })?;
-
-    let text = candidate.content.parts.iter().find_map(|p| p.text.clone());
-    let image = candidate
-        .content
-        .parts
-        .iter()
-        .find_map(|p| p.inline_data.as_ref())
-        .map(|data| general_purpose::STANDARD.decode(&data.data))
-        .transpose()
-        .map_err(|e| NanoError {
-            message: format!("Base64 Decode Error: {}", e),
-        })?;
-
-    if text.is_none() && image.is_none() {
+        .filter_map(|c| c.content)
+        .flat_map(|c| c.parts)
+    {
+        if let Some(text) = part.text {
+            // Streamed text arrives in fragments
+            match parts.last_mut() {
+                Some(OutputPart::Text(previous)) => previous.push_str(&text),
+                _ => parts.push(OutputPart::Text(text)),
+            }
+        }
+        if let Some(data) = part.inline_data {
+            let image = general_purpose::STANDARD
+                .decode(&data.data)
+                .map_err(|e| NanoError {
+                    message: format!("Base64 Decode Error: {}", e),
+                })?;
+            parts.push(OutputPart::Image(image));
+        }
+    }
+
+    if parts.is_empty() {
         return Err(NanoError {
             message: "Response contained no usable data.".to_string(),
         });
     }
 
-    let output = NanoOutput { text, image };
-    return Ok((output, tier_used));
+    Ok((NanoOutput { parts, usage }, tier_used))
 }
@@ -0,0 +1,159 @@
+use async_trait::async_trait;
+use twilight_interactions::command::{CommandModel, CommandOption, CreateCommand, CreateOption};
+use twilight_model::channel::message::{Embed, MessageFlags};
+use twilight_model::guild::Permissions;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::embed::EmbedFieldBuilder;
+
+use super::{CommandHandler, CommandHandlerData};
+use crate::utils::embed;
+
+#[derive(CommandOption, CreateOption, Clone, Copy)]
+enum HarmCategory {
+    #[option(name = "Harassment", value = "HARM_CATEGORY_HARASSMENT")]
+    Harassment,
+    #[option(name = "Hate speech", value = "HARM_CATEGORY_HATE_SPEECH")]
+    HateSpeech,
+    #[option(name = "Sexually explicit", value = "HARM_CATEGORY_SEXUALLY_EXPLICIT")]
+    SexuallyExplicit,
+    #[option(name = "Dangerous content", value = "HARM_CATEGORY_DANGEROUS_CONTENT")]
+    DangerousContent,
+    #[option(name = "Civic integrity", value = "HARM_CATEGORY_CIVIC_INTEGRITY")]
+    CivicIntegrity,
+}
+
+impl HarmCategory {
+    fn name(&self) -> &'static str {
+        match self {
+            HarmCategory::Harassment => "Harassment",
+            HarmCategory::HateSpeech => "Hate speech",
+            HarmCategory::SexuallyExplicit => "Sexually explicit",
+            HarmCategory::DangerousContent => "Dangerous content",
+            HarmCategory::CivicIntegrity => "Civic integrity",
+        }
+    }
+}
+
+const CATEGORIES: [HarmCategory; 5] = [
+    HarmCategory::Harassment,
+    HarmCategory::HateSpeech,
+    HarmCategory::SexuallyExplicit,
+    HarmCategory::DangerousContent,
+    HarmCategory::CivicIntegrity,
+];
+
+#[derive(CommandOption, CreateOption)]
+enum HarmThreshold {
+    #[option(name = "Model default", value = "default")]
+    Default,
+    #[option(name = "Block low and above", value = "BLOCK_LOW_AND_ABOVE")]
+    BlockLowAndAbove,
+    #[option(name = "Block medium and above", value = "BLOCK_MEDIUM_AND_ABOVE")]
+    BlockMediumAndAbove,
+    #[option(name = "Block only high", value = "BLOCK_ONLY_HIGH")]
+    BlockOnlyHigh,
+    #[option(name = "Block none", value = "BLOCK_NONE")]
+    BlockNone,
+    #[option(name = "Off", value = "OFF")]
+    Off,
+}
+
+fn manage_guild() -> Permissions {
+    Permissions::MANAGE_GUILD
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "safety",
+    desc = "Show or change the Gemini safety filters for this server",
+    default_permissions = "manage_guild",
+    dm_permission = false
+)]
+pub struct SafetyCommand {
+    /// Category to change. Leave both options empty to show the current settings.
+    category: Option<HarmCategory>,
+    /// When to block content in this category.
+    threshold: Option<HarmThreshold>,
+}
+
+#[async_trait]
+impl CommandHandler for SafetyCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str,
+    ) {
+        let response_embed = match self.update(&command_handler_data) {
+            Ok(()) => settings_embed(&command_handler_data),
+            Err(message) => embed::failure(&message).build(),
+        };
+
+        command_handler_data
+            .interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        embeds: Some(vec![response_embed]),
+                        flags: Some(MessageFlags::EPHEMERAL),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await
+            .ok();
+    }
+}
+
+impl SafetyCommand {
+    fn update(&self, command_handler_data: &CommandHandlerData<'_>) -> Result<(), String> {
+        let Some(guild_id) = command_handler_data.guild_id else {
+            return Err("Safety settings can only be changed in a server.".to_string());
+        };
+
+        let (category, threshold) = match (&self.category, &self.threshold) {
+            (None, None) => return Ok(()),
+            (Some(category), Some(threshold)) => (category, threshold),
+            _ => return Err("Choose both a category and a threshold.".to_string()),
+        };
+
+        let threshold = match threshold {
+            HarmThreshold::Default => None,
+            threshold => Some(threshold.value()),
+        };
+
+        command_handler_data
+            .storage
+            .set_safety_setting(guild_id.get(), category.value(), threshold)
+            .map_err(|e| e.message)
+    }
+}
+
+fn settings_embed(command_handler_data: &CommandHandlerData<'_>) -> Embed {
+    let settings = match command_handler_data.guild_id {
+        Some(guild_id) => match command_handler_data.storage.safety_settings(guild_id.get()) {
+            Ok(settings) => settings,
+            Err(e) => return embed::failure(&e.message).build(),
+        },
+        None => Vec::new(),
+    };
+
+    let mut settings_embed = embed::info().title("Gemini safety settings");
+    for category in CATEGORIES {
+        let threshold = settings
+            .iter()
+            .find(|(c, _)| c == category.value())
+            .map(|(_, t)| t.as_str())
+            .unwrap_or("Model default");
+        settings_embed =
+            settings_embed.field(EmbedFieldBuilder::new(category.name(), threshold).inline());
+    }
+    settings_embed.build()
+}
@@ -1,292 +1,488 @@
-use std::collections::{HashMap, HashSet};
-use std::time::Duration;
-
 use async_trait::async_trait;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use twilight_http::error::ErrorType;
 use twilight_interactions::command::{CommandModel, CreateCommand};
-use twilight_model::channel::Message;
-use twilight_model::http::interaction::{InteractionResponse, InteractionResponseType};
-use twilight_model::id::marker::{ChannelMarker, InteractionMarker, MessageMarker, UserMarker};
+use twilight_model::channel::message::{Embed, MessageFlags};
+use twilight_model::guild::Permissions;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::marker::{ChannelMarker, InteractionMarker, UserMarker};
 use twilight_model::id::Id;
-
+use twilight_util::builder::embed::EmbedFieldBuilder;
+use twilight_validate::embed::FIELD_VALUE_LENGTH;
+
+use self::leaderboard::{handle_page_button, leaderboard, PAGE_ID_PREFIX};
+use self::profile::player;
+use self::ranking::{game_date, LeaderboardPeriod, RankedDay, RankingMode};
+use self::sync::reparse_days;
+use self::sync::sync_channel;
 use super::{CommandHandler, CommandHandlerData};
+use crate::storage::{Storage, StorageError, WordleConfig};
 use crate::utils::embed;
 
-const TARGET_CHANNEL_ID: Id<ChannelMarker> = Id::new(946818381955366972);
-const TARGET_BOT_ID: Id<UserMarker> = Id::new(1211781489931452447);
+pub use self::sync::ingest_message;
+
+mod chart;
+mod leaderboard;
+mod parser;
+mod profile;
+mod ranking;
+mod sync;
+
+/// The score of a failed game, also given for days a player missed.
 const DEFAULT_SCORE: u32 = 7;
+/// Lowest and highest score a day-one override can set.
+const MIN_SCORE: i64 = 1;
+const MAX_SCORE: i64 = 7;
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "stats", desc = "Wordle leaderboard", dm_permission = false)]
+pub enum StatsCommand {
+    #[command(name = "leaderboard")]
+    Leaderboard(LeaderboardCommand),
+    #[command(name = "player")]
+    Player(PlayerCommand),
+    #[command(name = "setup")]
+    Setup(SetupCommand),
+    #[command(name = "alias")]
+    Alias(AliasCommand),
+    #[command(name = "override")]
+    Override(OverrideCommand),
+}
 
-static USER_ID_CAPTURE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"<@(\d+)>").unwrap());
-static USER_PATTERN_RE: Lazy<Regex> = Lazy::new(|| {
-    let user_mention_pattern = r"<@\d+>";
-    let mut patterns: Vec<String> = ALIASES.iter().map(|a| regex::escape(a.name)).collect();
-    patterns.push(user_mention_pattern.to_string());
-    Regex::new(&patterns.join("|")).unwrap()
-});
-
-pub struct Alias {
-    pub name: &'static str,
-    pub id: u64,
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "leaderboard", desc = "Compute the Wordle leaderboard")]
+pub struct LeaderboardCommand {
+    /// Days to include. Uses all time by default, or the last N days if days is set.
+    period: Option<LeaderboardPeriod>,
+    /// Number of days for "Last N days". Uses 30 by default.
+    #[command(min_value = 1, max_value = 365)]
+    days: Option<i64>,
+    /// How to rank players. Uses average score by default.
+    ranking: Option<RankingMode>,
 }
 
-const ALIASES: &[Alias] = &[
-    Alias {
-        name: "@rmanky",
-        id: 150725833957441536,
-    },
-    Alias {
-        name: "@Raúl 3.0",
-        id: 302973340371517441,
-    },
-    Alias {
-        name: "@troyotter",
-        id: 481280459058184204,
-    },
-    Alias {
-        name: "@aaron_",
-        id: 656347629524877312,
-    },
-];
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "player", desc = "Show a player's Wordle record")]
+pub struct PlayerCommand {
+    /// Player to show. Shows you by default.
+    user: Option<Id<UserMarker>>,
+}
 
 #[derive(CommandModel, CreateCommand)]
-#[command(name = "stats", desc = "Compute the Wordle leaderboard")]
-pub struct StatsCommand {}
-
-struct PlayerStats {
-    user_id: String,
-    penalized_score: u32,
-    average_score: f32,
-    days_played: usize,
+#[command(
+    name = "setup",
+    desc = "Choose where Wordle results are posted. Requires Manage Server."
+)]
+pub struct SetupCommand {
+    /// Channel the daily results are posted in.
+    #[command(channel_types = "guild_text")]
+    channel: Id<ChannelMarker>,
+    /// Bot that posts the daily results.
+    bot: Id<UserMarker>,
 }
 
-async fn get_all_messages(
-    data: &CommandHandlerData<'_>,
-) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
-    let mut all_messages = Vec::new();
-    let mut last_message_id: Option<Id<MessageMarker>> = None;
-    let mut num_messages_crawled = 0;
-
-    'outer: loop {
-        let result = if let Some(message_id) = last_message_id {
-            data.twilight_client
-                .channel_messages(TARGET_CHANNEL_ID)
-                .before(message_id)
-                .limit(100)
-                .await
-        } else {
-            data.twilight_client
-                .channel_messages(TARGET_CHANNEL_ID)
-                .limit(100)
-                .await
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "alias",
+    desc = "Match a name in the results to a member. Requires Manage Server."
+)]
+pub struct AliasCommand {
+    /// Name as the results bot writes it, e.g. @rmanky.
+    name: String,
+    /// Member the name belongs to. Leave empty to remove the alias.
+    user: Option<Id<UserMarker>>,
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "override",
+    desc = "Set a player's score for the first tracked day. Requires Manage Server."
+)]
+pub struct OverrideCommand {
+    /// Player to set the score of.
+    user: Id<UserMarker>,
+    /// Score for the first day, where 7 is a failed game. Leave empty to remove the override.
+    #[command(min_value = 1, max_value = 7)]
+    score: Option<i64>,
+}
+
+#[async_trait]
+impl CommandHandler for StatsCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str,
+    ) {
+        let Some(guild_id) = command_handler_data.guild_id else {
+            return;
         };
 
-        let messages: Vec<Message> = match result {
-            Ok(response) => response.model().await?,
-            Err(e) => {
-                if let ErrorType::Response { body, status, .. } = e.kind() {
-                    if *status == 429 {
-                        let body_json: serde_json::Value = serde_json::from_slice(body)?;
-                        let retry_after = body_json["retry_after"].as_f64().unwrap_or(0.5);
-                        log::warn!("Rate limited, retrying after {}s", retry_after);
-                        tokio::time::sleep(Duration::from_secs_f64(retry_after)).await;
-                        continue;
-                    }
-                }
-                return Err(e.into());
+        let allowed = command_handler_data
+            .member_permissions
+            .is_some_and(|p| p.contains(Permissions::MANAGE_GUILD));
+        let result = match self {
+            StatsCommand::Leaderboard(leaderboard_command) => {
+                leaderboard(
+                    &command_handler_data,
+                    guild_id.get(),
+                    leaderboard_command,
+                    interaction_id,
+                    interaction_token,
+                )
+                .await;
+                return;
+            }
+            StatsCommand::Player(player_command) => {
+                player(
+                    &command_handler_data,
+                    guild_id.get(),
+                    player_command,
+                    interaction_id,
+                    interaction_token,
+                )
+                .await;
+                return;
+            }
+            _ if !allowed => {
+                Err("You need the Manage Server permission to change this.".to_string())
+            }
+            StatsCommand::Setup(setup) => command_handler_data
+                .storage
+                .set_wordle_channel(guild_id.get(), setup.channel.get(), setup.bot.get())
+                .map_err(|e| e.to_string()),
+            StatsCommand::Alias(alias) => set_alias(&command_handler_data, guild_id.get(), alias),
+            StatsCommand::Override(override_command) => {
+                set_override(&command_handler_data, guild_id.get(), override_command)
             }
         };
 
-        if messages.is_empty() {
-            break;
-        }
+        let response_embed = match result.and_then(|_| {
+            command_handler_data
+                .storage
+                .wordle_config(guild_id.get())
+                .map_err(|e| e.to_string())
+        }) {
+            Ok(Some(config)) => config_embed(&config),
+            Ok(None) => not_set_up_embed(),
+            Err(message) => embed::failure(&message).build(),
+        };
 
-        num_messages_crawled += messages.len();
-        last_message_id = messages.last().map(|m| m.id);
+        respond_ephemeral(
+            &command_handler_data,
+            interaction_id,
+            interaction_token,
+            response_embed,
+        )
+        .await;
+    }
+}
 
-        for message in messages {
-            if message.author.id != TARGET_BOT_ID {
-                continue;
-            }
-            if message.content.contains("Your group is on a 1 day streak") {
-                all_messages.push(message.content);
-                break 'outer;
-            } else if message.content.contains("Your group is on") {
-                all_messages.push(message.content);
-            }
-        }
+/// Handles the page buttons under a leaderboard.
+pub async fn handle_component(
+    command_handler_data: CommandHandlerData<'_>,
+    custom_id: &str,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &'_ str,
+) {
+    let Some(guild_id) = command_handler_data.guild_id else {
+        return;
+    };
+    if custom_id.starts_with(PAGE_ID_PREFIX) {
+        handle_page_button(
+            &command_handler_data,
+            guild_id.get(),
+            custom_id,
+            interaction_id,
+            interaction_token,
+        )
+        .await;
     }
+}
 
-    log::info!("Fetched {} relevant messages.", all_messages.len());
-    log::info!("Crawled {} messages.", num_messages_crawled);
-    Ok(all_messages)
+fn set_alias(
+    data: &CommandHandlerData<'_>,
+    guild_id: u64,
+    alias: &AliasCommand,
+) -> Result<(), String> {
+    let name = alias.name.trim();
+    // The results bot writes unmentioned players as @name
+    let name = match name.starts_with('@') {
+        true => name.to_string(),
+        false => format!("@{}", name),
+    };
+    require_setup(data, guild_id)?;
+    data.storage
+        .set_wordle_alias(guild_id, &name, alias.user.map(Id::get))
+        .and_then(|_| reparse_days(&data.storage, guild_id))
+        .map_err(|e| e.to_string())
 }
 
-fn get_user_id_from_token(token: &str) -> Option<String> {
-    if let Some(id_match) = USER_ID_CAPTURE_RE.captures(token).and_then(|c| c.get(1)) {
-        return Some(id_match.as_str().to_string());
-    }
+fn set_override(
+    data: &CommandHandlerData<'_>,
+    guild_id: u64,
+    override_command: &OverrideCommand,
+) -> Result<(), String> {
+    require_setup(data, guild_id)?;
+    let score = override_command
+        .score
+        .map(|score| score.clamp(MIN_SCORE, MAX_SCORE) as u32);
+    data.storage
+        .set_wordle_override(guild_id, override_command.user.get(), score)
+        .map_err(|e| e.to_string())
+}
 
-    if let Some(alias) = ALIASES.iter().find(|a| a.name == token) {
-        return Some(alias.id.to_string());
+fn require_setup(data: &CommandHandlerData<'_>, guild_id: u64) -> Result<(), String> {
+    match data.storage.wordle_config(guild_id) {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err("Run /stats setup first.".to_string()),
+        Err(e) => Err(e.to_string()),
     }
-
-    log::warn!("Could not resolve token to user ID: '{}'", token);
-    None
 }
 
-#[async_trait]
-impl CommandHandler for StatsCommand {
-    async fn handle_command(
-        &self,
-        command_handler_data: CommandHandlerData<'_>,
-        interaction_id: Id<InteractionMarker>,
-        interaction_token: &'_ str,
-    ) {
-        command_handler_data
-            .interaction_client
-            .create_response(
+/// Reads any new results, then loads every tracked day, oldest first, with
+/// the day-one overrides applied. The response is deferred, so callers finish
+/// it with `update_response`. Returns `None` once it has responded itself.
+async fn load_days(
+    command_handler_data: &CommandHandlerData<'_>,
+    guild_id: u64,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &str,
+) -> Option<Vec<RankedDay>> {
+    let config = match command_handler_data.storage.wordle_config(guild_id) {
+        Ok(Some(config)) => config,
+        Err(e) => {
+            log::error!("Failed to load Wordle settings: {}", e);
+            respond_ephemeral(
+                command_handler_data,
+                interaction_id,
+                interaction_token,
+                embed::failure(&e.to_string()).build(),
+            )
+            .await;
+            return None;
+        }
+        Ok(None) => {
+            respond_ephemeral(
+                command_handler_data,
                 interaction_id,
                 interaction_token,
-                &InteractionResponse {
-                    kind: InteractionResponseType::DeferredChannelMessageWithSource,
-                    data: None,
-                },
+                not_set_up_embed(),
             )
+            .await;
+            return None;
+        }
+    };
+
+    command_handler_data
+        .interaction_client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: None,
+            },
+        )
+        .await
+        .ok();
+
+    if let Err(e) = sync_channel(command_handler_data, guild_id, &config).await {
+        let error_msg = format!("Failed to fetch messages: {}", e);
+        log::error!("{}", error_msg);
+        let err_embed = embed::failure(&error_msg).build();
+        command_handler_data
+            .interaction_client
+            .update_response(interaction_token)
+            .embeds(Some(&[err_embed]))
             .await
             .ok();
+        return None;
+    }
 
-        let messages = match get_all_messages(&command_handler_data).await {
-            Ok(messages) => messages,
-            Err(e) => {
-                let error_msg = format!("Failed to fetch messages: {}", e);
-                log::error!("{}", error_msg);
-                let err_embed = embed::failure(&error_msg).build();
-                command_handler_data
-                    .interaction_client
-                    .update_response(interaction_token)
-                    .embeds(Some(&[err_embed]))
-                    .await
-                    .ok();
-                return;
-            }
-        };
-
-        if messages.is_empty() {
-            let empty_embed = embed::success()
-                .description("Found no score messages!")
-                .build();
+    let ranked_days = match stored_days(&command_handler_data.storage, guild_id) {
+        Ok(ranked_days) => ranked_days.unwrap_or_default(),
+        Err(e) => {
+            log::error!("Failed to load Wordle results: {}", e);
             command_handler_data
                 .interaction_client
                 .update_response(interaction_token)
-                .embeds(Some(&[empty_embed]))
+                .embeds(Some(&[embed::failure(&e.to_string()).build()]))
                 .await
                 .ok();
-            return;
-        }
-
-        // Vec<day, HashMap<user_id, score>>
-        let mut daily_results: Vec<HashMap<String, u32>> = Vec::new();
-        // HashSet<user_id>
-        let mut all_participants: HashSet<String> = HashSet::new();
-
-        for message in messages.iter().rev() {
-            let mut daily_scores: HashMap<String, u32> = HashMap::new();
-            for line in message.split('\n').skip(1) {
-                let cleaned_line = line.replace("\\", "");
-                let parts: Vec<&str> = cleaned_line.split(':').collect();
-                if parts.len() < 2 {
-                    continue;
-                }
-
-                let score_part = parts[0];
-                let users_part = parts[1];
-
-                let score = score_part
-                    .chars()
-                    .find(|c| c.is_ascii_digit() || *c == 'X')
-                    .and_then(|c| c.to_digit(10))
-                    .unwrap_or(DEFAULT_SCORE);
-
-                for mat in USER_PATTERN_RE.find_iter(users_part) {
-                    if let Some(user_id) = get_user_id_from_token(mat.as_str()) {
-                        daily_scores.insert(user_id.clone(), score);
-                        all_participants.insert(user_id);
-                    }
-                }
-            }
-            daily_results.push(daily_scores);
+            return None;
         }
+    };
 
-        // Day one overrides
-        daily_results[0].extend(HashMap::from([
-            ("302973340371517441".to_string(), 4), // Raúl 3.0
-            ("150725833957441536".to_string(), 6), // rmanky
-            ("481280459058184204".to_string(), 6), // troyotter
-        ]));
-
-        let mut leaderboard: Vec<PlayerStats> = all_participants
-            .into_iter()
-            .map(|user_id| {
-                let mut total_score: u32 = 0;
-                let mut penalized_score: u32 = 0;
-                let mut days_played: usize = 0;
-
-                for day in &daily_results {
-                    if let Some(score) = day.get(&user_id) {
-                        total_score += *score;
-                        penalized_score += *score;
-                        days_played += 1;
-                    } else {
-                        // User didn't play, add penalty
-                        penalized_score += DEFAULT_SCORE;
-                    }
-                }
-
-                let average_score = if days_played > 0 {
-                    total_score as f32 / days_played as f32
-                } else {
-                    0.0
-                };
-
-                PlayerStats {
-                    user_id,
-                    penalized_score,
-                    average_score,
-                    days_played,
-                }
-            })
-            .collect();
-
-        // Sort by average score, ascending (lower is better).
-        leaderboard.sort_by(|a, b| a.average_score.partial_cmp(&b.average_score).unwrap());
-
-        let description = leaderboard
-            .iter()
-            .enumerate()
-            .map(|(i, stats)| {
-                format!(
-                    "**{}.** <@{}> Avg: **{:.2}** (Total: {}, Days: {})\n",
-                    i + 1,
-                    stats.user_id,
-                    stats.average_score,
-                    stats.penalized_score,
-                    stats.days_played
-                )
-            })
-            .collect::<String>();
-
-        let final_embed = embed::success()
-            .title("Wordle Leaderboard")
-            .description(&description)
+    if ranked_days.is_empty() {
+        let empty_embed = embed::success()
+            .description("Found no score messages!")
             .build();
-
         command_handler_data
             .interaction_client
             .update_response(interaction_token)
-            .embeds(Some(&[final_embed]))
+            .embeds(Some(&[empty_embed]))
             .await
             .ok();
+        return None;
+    }
+    Some(ranked_days)
+}
+
+/// Every stored day, oldest first, with the day-one overrides applied, or
+/// `None` if the guild has not been set up.
+fn stored_days(storage: &Storage, guild_id: u64) -> Result<Option<Vec<RankedDay>>, StorageError> {
+    let Some(config) = storage.wordle_config(guild_id)? else {
+        return Ok(None);
+    };
+    let mut ranked_days: Vec<RankedDay> = storage
+        .wordle_days(guild_id)?
+        .into_iter()
+        .map(|d| RankedDay {
+            date: game_date(d.message_id),
+            scores: d.scores,
+        })
+        .collect();
+    if let Some(first_day) = ranked_days.first_mut() {
+        first_day
+            .scores
+            .extend(config.day_one_overrides.iter().copied());
+    }
+    Ok(Some(ranked_days))
+}
+
+fn not_set_up_embed() -> Embed {
+    embed::info()
+        .title("Wordle tracking")
+        .description(
+            "Wordle tracking is not set up in this server. \
+            Someone with the Manage Server permission can run /stats setup.",
+        )
+        .build()
+}
+
+fn config_embed(config: &WordleConfig) -> Embed {
+    let aliases: Vec<String> = config
+        .aliases
+        .iter()
+        .map(|a| format!("`{}` → <@{}>", a.name, a.user_id))
+        .collect();
+    let overrides: Vec<String> = config
+        .day_one_overrides
+        .iter()
+        .map(|(user_id, score)| format!("<@{}>: {}", user_id, score))
+        .collect();
+
+    embed::info()
+        .title("Wordle tracking")
+        .field(EmbedFieldBuilder::new("Channel", format!("<#{}>", config.channel_id)).inline())
+        .field(EmbedFieldBuilder::new("Results bot", format!("<@{}>", config.bot_id)).inline())
+        .field(EmbedFieldBuilder::new("Aliases", field_value(&aliases)))
+        .field(EmbedFieldBuilder::new(
+            "Day one scores",
+            field_value(&overrides),
+        ))
+        .build()
+}
+
+/// `lines`, one per line, cut short with a count of the rest where they would
+/// go over Discord's limit for a field.
+fn field_value(lines: &[String]) -> String {
+    if lines.is_empty() {
+        return "None".to_string();
+    }
+
+    let mut value = String::new();
+    let mut length = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let separator = match value.is_empty() {
+            true => "",
+            false => "\n",
+        };
+        // Leave room to say how many lines are left after this one
+        let remaining = lines.len() - i - 1;
+        let reserved = match remaining {
+            0 => 0,
+            remaining => more_line(remaining).chars().count() + 1,
+        };
+        let line_length = separator.len() + line.chars().count();
+        if length + line_length + reserved > FIELD_VALUE_LENGTH {
+            value += separator;
+            value += &more_line(remaining + 1);
+            break;
+        }
+        value += separator;
+        value += line;
+        length += line_length;
+    }
+    value
+}
+
+fn more_line(count: usize) -> String {
+    format!("… and {} more", count)
+}
+
+async fn respond_ephemeral(
+    data: &CommandHandlerData<'_>,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &str,
+    response_embed: Embed,
+) {
+    data.interaction_client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(InteractionResponseData {
+                    embeds: Some(vec![response_embed]),
+                    flags: Some(MessageFlags::EPHEMERAL),
+                    ..Default::default()
+                }),
+            },
+        )
+        .await
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::WordleAlias;
+
+    fn config(alias_count: u64) -> WordleConfig {
+        WordleConfig {
+            channel_id: 1,
+            bot_id: 2,
+            aliases: (0..alias_count)
+                .map(|i| WordleAlias {
+                    name: format!("player {}", i),
+                    user_id: 100_000_000_000_000_000 + i,
+                })
+                .collect(),
+            day_one_overrides: Vec::new(),
+            last_message_id: None,
+        }
+    }
+
+    fn field<'a>(embed: &'a Embed, name: &str) -> &'a str {
+        &embed.fields.iter().find(|f| f.name == name).unwrap().value
+    }
+
+    #[test]
+    fn lists_every_alias_that_fits() {
+        let embed = config_embed(&config(3));
+        assert_eq!(field(&embed, "Aliases").lines().count(), 3);
+        assert_eq!(field(&embed, "Day one scores"), "None");
+    }
+
+    #[test]
+    fn cuts_long_alias_lists_short() {
+        let embed = config_embed(&config(200));
+        let aliases = field(&embed, "Aliases");
+        assert!(aliases.chars().count() <= FIELD_VALUE_LENGTH);
+
+        let shown = aliases.lines().count() - 1;
+        assert_eq!(aliases.lines().last().unwrap(), more_line(200 - shown));
     }
 }
@@ -0,0 +1,242 @@
+//! PNG charts for the leaderboard and player profiles.
+//!
+//! There is no font renderer in the tree, so the few labels the charts need
+//! (scores and counts) are drawn from a small pixel font, and players are told
+//! apart by the colored squares next to their names in the embed.
+
+use std::io::Cursor;
+
+use image::{ImageError, ImageFormat, Rgba, RgbaImage};
+
+use super::ranking::{RankedDay, Window};
+use super::DEFAULT_SCORE;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 400;
+const MARGIN: u32 = 40;
+/// Pixels per pixel-font dot.
+const TEXT_SCALE: u32 = 3;
+const LINE_RADIUS: i64 = 2;
+
+const BACKGROUND: Rgba<u8> = Rgba([43, 45, 49, 255]);
+const GRID: Rgba<u8> = Rgba([64, 66, 73, 255]);
+const LABEL: Rgba<u8> = Rgba([181, 186, 193, 255]);
+const BAR: Rgba<u8> = Rgba([120, 177, 89, 255]);
+const FAILED_BAR: Rgba<u8> = Rgba([221, 46, 68, 255]);
+
+/// Line colors, matching `SWATCHES`.
+const PALETTE: [Rgba<u8>; 8] = [
+    Rgba([221, 46, 68, 255]),
+    Rgba([85, 172, 238, 255]),
+    Rgba([120, 177, 89, 255]),
+    Rgba([253, 203, 88, 255]),
+    Rgba([170, 142, 214, 255]),
+    Rgba([244, 144, 12, 255]),
+    Rgba([193, 105, 79, 255]),
+    Rgba([230, 231, 232, 255]),
+];
+/// Emoji shown next to the player drawn in the matching `PALETTE` color.
+pub(super) const SWATCHES: [&str; 8] = ["🟥", "🟦", "🟩", "🟨", "🟪", "🟧", "🟫", "⬜"];
+
+/// A 3x5 pixel font, one row per byte.
+const GLYPHS: [(char, [u8; 5]); 11] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+];
+
+/// Each player's running average score across the days in `window`, one
+/// line per player in `user_ids` order. The best score is at the top.
+pub(super) fn trend_chart(days: &[RankedDay], window: Window, user_ids: &[u64]) -> RgbaImage {
+    let mut chart = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+    let left = MARGIN as i64;
+    let right = (WIDTH - MARGIN) as i64;
+    let y_of = |score: f64| {
+        let top = MARGIN as f64;
+        let bottom = (HEIGHT - MARGIN) as f64;
+        (top + (score - 1.0) / (DEFAULT_SCORE - 1) as f64 * (bottom - top)).round() as i64
+    };
+
+    for score in 1..=DEFAULT_SCORE {
+        let y = y_of(score as f64);
+        draw_line(&mut chart, (left, y), (right, y), 0, GRID);
+        draw_text(&mut chart, &score_label(score), 10, y - 7, LABEL);
+    }
+
+    let days: Vec<&RankedDay> = days.iter().filter(|d| window.contains(d.date)).collect();
+    let x_of = |i: usize| match days.len() {
+        0 | 1 => (left + right) / 2,
+        len => left + (right - left) * i as i64 / (len - 1) as i64,
+    };
+
+    for (user_id, color) in user_ids.iter().zip(PALETTE) {
+        let (mut played, mut total) = (0, 0);
+        let mut previous = None;
+        for (i, day) in days.iter().enumerate() {
+            let Some(&score) = day.scores.get(user_id) else {
+                continue;
+            };
+            played += 1;
+            total += score;
+            let point = (x_of(i), y_of(total as f64 / played as f64));
+            draw_line(
+                &mut chart,
+                previous.unwrap_or(point),
+                point,
+                LINE_RADIUS,
+                color,
+            );
+            previous = Some(point);
+        }
+    }
+
+    chart
+}
+
+/// Bars for games won in 1 to 6 guesses and failed games, with counts above.
+pub(super) fn distribution_chart(distribution: &[usize; DEFAULT_SCORE as usize]) -> RgbaImage {
+    let mut chart = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+    let most = distribution.iter().copied().max().unwrap_or(0).max(1);
+    let slot = (WIDTH - 2 * MARGIN) / distribution.len() as u32;
+    let baseline = (HEIGHT - MARGIN) as i64;
+    // Room for the count above the tallest bar
+    let tallest = (HEIGHT - 3 * MARGIN) as usize;
+
+    for (i, &count) in distribution.iter().enumerate() {
+        let score = i as u32 + 1;
+        let center = (MARGIN + slot * i as u32 + slot / 2) as i64;
+        let height = (count * tallest / most) as i64;
+        let color = match score {
+            DEFAULT_SCORE => FAILED_BAR,
+            _ => BAR,
+        };
+        fill_rect(
+            &mut chart,
+            center - slot as i64 / 3,
+            baseline - height,
+            center + slot as i64 / 3,
+            baseline,
+            color,
+        );
+
+        let label = score_label(score);
+        draw_text(
+            &mut chart,
+            &label,
+            center - text_width(&label) / 2,
+            baseline + 10,
+            LABEL,
+        );
+        let count = count.to_string();
+        draw_text(
+            &mut chart,
+            &count,
+            center - text_width(&count) / 2,
+            baseline - height - 25,
+            LABEL,
+        );
+    }
+
+    chart
+}
+
+pub(super) fn encode_png(chart: &RgbaImage) -> Result<Vec<u8>, ImageError> {
+    let mut buf = Cursor::new(Vec::new());
+    chart.write_to(&mut buf, ImageFormat::Png)?;
+    Ok(buf.into_inner())
+}
+
+fn score_label(score: u32) -> String {
+    match score {
+        DEFAULT_SCORE => "X".to_string(),
+        score => score.to_string(),
+    }
+}
+
+fn text_width(text: &str) -> i64 {
+    (text.chars().count() as i64 * 4 - 1) * TEXT_SCALE as i64
+}
+
+fn draw_text(chart: &mut RgbaImage, text: &str, x: i64, y: i64, color: Rgba<u8>) {
+    let scale = TEXT_SCALE as i64;
+    for (i, c) in text.chars().enumerate() {
+        let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == c) else {
+            continue;
+        };
+        let glyph_x = x + i as i64 * 4 * scale;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    let dot_x = glyph_x + column * scale;
+                    let dot_y = y + row as i64 * scale;
+                    fill_rect(chart, dot_x, dot_y, dot_x + scale, dot_y + scale, color);
+                }
+            }
+        }
+    }
+}
+
+/// Fills from (x0, y0) up to but not including (x1, y1), clipped to the chart.
+fn fill_rect(chart: &mut RgbaImage, x0: i64, y0: i64, x1: i64, y1: i64, color: Rgba<u8>) {
+    let (width, height) = (chart.width() as i64, chart.height() as i64);
+    for y in y0.max(0)..y1.min(height) {
+        for x in x0.max(0)..x1.min(width) {
+            chart.put_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+/// Draws a line `radius` pixels either side of the path from `from` to `to`.
+fn draw_line(
+    chart: &mut RgbaImage,
+    from: (i64, i64),
+    to: (i64, i64),
+    radius: i64,
+    color: Rgba<u8>,
+) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).max(1);
+    for step in 0..=steps {
+        let x = from.0 + (to.0 - from.0) * step / steps;
+        let y = from.1 + (to.1 - from.1) * step / steps;
+        fill_rect(
+            chart,
+            x - radius,
+            y - radius,
+            x + radius + 1,
+            y + radius + 1,
+            color,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::stats::ranking::tests::day;
+
+    #[test]
+    fn draws_each_player_in_their_color() {
+        let days: Vec<RankedDay> = (0..5).map(|date| day(date, &[(1, 2), (2, 6)])).collect();
+        let chart = trend_chart(&days, Window { start: None }, &[1, 2]);
+        assert_eq!(chart.dimensions(), (WIDTH, HEIGHT));
+        for color in &PALETTE[..2] {
+            assert!(chart.pixels().any(|pixel| pixel == color));
+        }
+        assert!(!chart.pixels().any(|pixel| *pixel == PALETTE[2]));
+    }
+
+    #[test]
+    fn encodes_png() {
+        let png = encode_png(&distribution_chart(&[0, 1, 4, 2, 0, 0, 1])).unwrap();
+        let decoded = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (WIDTH, HEIGHT));
+    }
+}
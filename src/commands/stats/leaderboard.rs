@@ -0,0 +1,277 @@
+use twilight_model::channel::message::component::{ActionRow, Button, ButtonStyle};
+use twilight_model::channel::message::{Component, Embed};
+use twilight_model::http::attachment::Attachment;
+use twilight_model::http::interaction::{InteractionResponse, InteractionResponseType};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::embed::{EmbedFooterBuilder, ImageSource};
+
+use super::chart::{encode_png, trend_chart, SWATCHES};
+use super::ranking::{
+    rank, today, LeaderboardPeriod, PlayerStats, RankedDay, RankingMode, Window,
+    DEFAULT_WINDOW_DAYS,
+};
+use super::{load_days, not_set_up_embed, stored_days, LeaderboardCommand};
+use crate::commands::CommandHandlerData;
+use crate::storage::unix_now;
+use crate::utils::embed;
+
+/// Players per page, one for each chart color.
+const PAGE_SIZE: usize = SWATCHES.len();
+pub(super) const PAGE_ID_PREFIX: &str = "stats:leaderboard:";
+const CHART_FILENAME: &str = "leaderboard.png";
+
+/// What a leaderboard shows, kept in its page buttons so any page can be
+/// rebuilt from storage.
+#[derive(Clone, Copy)]
+struct LeaderboardOptions {
+    period: LeaderboardPeriod,
+    days: u32,
+    mode: RankingMode,
+}
+
+impl LeaderboardOptions {
+    fn new(command: &LeaderboardCommand) -> Self {
+        let period = match (command.period, command.days) {
+            (Some(period), _) => period,
+            (None, Some(_)) => LeaderboardPeriod::LastDays,
+            (None, None) => LeaderboardPeriod::AllTime,
+        };
+        LeaderboardOptions {
+            period,
+            days: command
+                .days
+                .map_or(DEFAULT_WINDOW_DAYS, |days| days.clamp(1, 365) as u32),
+            mode: command.ranking.unwrap_or(RankingMode::Average),
+        }
+    }
+
+    fn page_id(&self, page: usize) -> String {
+        let period = match self.period {
+            LeaderboardPeriod::ThisWeek => "week",
+            LeaderboardPeriod::ThisMonth => "month",
+            LeaderboardPeriod::LastDays => "days",
+            LeaderboardPeriod::AllTime => "all",
+        };
+        let mode = match self.mode {
+            RankingMode::Average => "average",
+            RankingMode::Total => "total",
+            RankingMode::Wins => "wins",
+            RankingMode::Rating => "rating",
+        };
+        format!(
+            "{}{}:{}:{}:{}",
+            PAGE_ID_PREFIX, period, self.days, mode, page
+        )
+    }
+
+    fn parse_page_id(custom_id: &str) -> Option<(Self, usize)> {
+        let mut parts = custom_id.strip_prefix(PAGE_ID_PREFIX)?.split(':');
+        let period = match parts.next()? {
+            "week" => LeaderboardPeriod::ThisWeek,
+            "month" => LeaderboardPeriod::ThisMonth,
+            "days" => LeaderboardPeriod::LastDays,
+            "all" => LeaderboardPeriod::AllTime,
+            _ => return None,
+        };
+        let days = parts.next()?.parse().ok()?;
+        let mode = match parts.next()? {
+            "average" => RankingMode::Average,
+            "total" => RankingMode::Total,
+            "wins" => RankingMode::Wins,
+            "rating" => RankingMode::Rating,
+            _ => return None,
+        };
+        let page = parts.next()?.parse().ok()?;
+        Some((LeaderboardOptions { period, days, mode }, page))
+    }
+
+    fn period_name(&self) -> String {
+        match self.period {
+            LeaderboardPeriod::ThisWeek => "this week".to_string(),
+            LeaderboardPeriod::ThisMonth => "this month".to_string(),
+            LeaderboardPeriod::LastDays => format!("last {} days", self.days),
+            LeaderboardPeriod::AllTime => "all time".to_string(),
+        }
+    }
+}
+
+pub(super) async fn leaderboard(
+    command_handler_data: &CommandHandlerData<'_>,
+    guild_id: u64,
+    command: &LeaderboardCommand,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &str,
+) {
+    let Some(ranked_days) = load_days(
+        command_handler_data,
+        guild_id,
+        interaction_id,
+        interaction_token,
+    )
+    .await
+    else {
+        return;
+    };
+
+    let (page_embed, components, attachments) =
+        render_page(&ranked_days, LeaderboardOptions::new(command), 0);
+    command_handler_data
+        .interaction_client
+        .update_response(interaction_token)
+        .embeds(Some(&[page_embed]))
+        .components(Some(&components))
+        .attachments(&attachments)
+        .await
+        .ok();
+}
+
+/// Shows another page of a leaderboard when one of its buttons is pressed.
+pub(super) async fn handle_page_button(
+    command_handler_data: &CommandHandlerData<'_>,
+    guild_id: u64,
+    custom_id: &str,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &str,
+) {
+    let Some((options, page)) = LeaderboardOptions::parse_page_id(custom_id) else {
+        return;
+    };
+
+    command_handler_data
+        .interaction_client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredUpdateMessage,
+                data: None,
+            },
+        )
+        .await
+        .ok();
+
+    let (page_embed, components, attachments) =
+        match stored_days(&command_handler_data.storage, guild_id) {
+            Ok(Some(ranked_days)) => render_page(&ranked_days, options, page),
+            Ok(None) => (not_set_up_embed(), Vec::new(), Vec::new()),
+            Err(e) => {
+                log::error!("Failed to load Wordle results: {}", e);
+                (
+                    embed::failure(&e.to_string()).build(),
+                    Vec::new(),
+                    Vec::new(),
+                )
+            }
+        };
+    command_handler_data
+        .interaction_client
+        .update_response(interaction_token)
+        .embeds(Some(&[page_embed]))
+        .components(Some(&components))
+        .attachments(&attachments)
+        .await
+        .ok();
+}
+
+/// Builds one page of the leaderboard, with a chart of the players on it and
+/// buttons for the neighbouring pages.
+fn render_page(
+    ranked_days: &[RankedDay],
+    options: LeaderboardOptions,
+    page: usize,
+) -> (Embed, Vec<Component>, Vec<Attachment>) {
+    let window = Window::new(options.period, options.days, today(unix_now()));
+    let ranking = rank(ranked_days, window, options.mode);
+    let page_count = ranking.len().div_ceil(PAGE_SIZE).max(1);
+    let page = page.min(page_count - 1);
+    let players: Vec<(usize, &PlayerStats)> = ranking
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .collect();
+
+    let description = match players.is_empty() {
+        true => "Nobody played in this period.".to_string(),
+        false => players
+            .iter()
+            .zip(SWATCHES)
+            .map(|((i, stats), swatch)| {
+                format!(
+                    "{} **{}.** <@{}> {}\n",
+                    swatch,
+                    i + 1,
+                    stats.user_id,
+                    line(stats, options.mode)
+                )
+            })
+            .collect::<String>(),
+    };
+
+    let mut footer = format!("Ranked by {}", options.mode.name());
+    if page_count > 1 {
+        footer += &format!(" · Page {} of {}", page + 1, page_count);
+    }
+    let mut page_embed = embed::success()
+        .title(format!("Wordle Leaderboard, {}", options.period_name()))
+        .description(&description)
+        .footer(EmbedFooterBuilder::new(footer));
+
+    let mut attachments = Vec::new();
+    if !players.is_empty() {
+        let user_ids: Vec<u64> = players.iter().map(|(_, stats)| stats.user_id).collect();
+        match encode_png(&trend_chart(ranked_days, window, &user_ids)) {
+            Ok(png) => {
+                page_embed = page_embed.image(ImageSource::attachment(CHART_FILENAME).unwrap());
+                attachments.push(Attachment::from_bytes(CHART_FILENAME.to_string(), png, 1));
+            }
+            Err(e) => log::warn!("Failed to render the leaderboard chart: {}", e),
+        }
+    }
+
+    let components = match page_count {
+        1 => Vec::new(),
+        _ => vec![Component::ActionRow(ActionRow {
+            components: vec![
+                page_button(&options, page.checked_sub(1), "Previous"),
+                page_button(&options, Some(page + 1).filter(|&p| p < page_count), "Next"),
+            ],
+        })],
+    };
+
+    (page_embed.build(), components, attachments)
+}
+
+/// A button to `page`, or a disabled one if there is no such page.
+fn page_button(options: &LeaderboardOptions, page: Option<usize>, label: &str) -> Component {
+    Component::Button(Button {
+        // Disabled buttons still need an id, and the two must differ
+        custom_id: Some(match page {
+            Some(page) => options.page_id(page),
+            None => format!("{}:{}", options.page_id(0), label),
+        }),
+        disabled: page.is_none(),
+        emoji: None,
+        label: Some(label.to_string()),
+        style: ButtonStyle::Secondary,
+        url: None,
+        sku_id: None,
+    })
+}
+
+/// A player's stats, with the one they are ranked by first.
+fn line(stats: &PlayerStats, mode: RankingMode) -> String {
+    let average = format!("Avg: {:.2}", stats.average_score);
+    let total = format!("Total: {}", stats.penalized_score);
+    let wins = format!("Wins: {}", stats.wins);
+    let rating = format!("Rating: {:.0}", stats.rating);
+    let days = format!("Days: {}", stats.days_played);
+    let (primary, rest) = match mode {
+        RankingMode::Average => (average, [total, wins, days]),
+        RankingMode::Total => (total, [average, wins, days]),
+        RankingMode::Wins => (wins, [average, total, days]),
+        RankingMode::Rating => (rating, [average, wins, days]),
+    };
+    format!("**{}** ({})", primary, rest.join(", "))
+}
@@ -0,0 +1,302 @@
+//! Reads the daily summary the Wordle app posts, which looks like:
+//!
+//! ```text
+//! **Your group is on a 12 day streak!** 🔥 Here are yesterday's results:
+//! 👑 3/6: <@150725833957441536> @Raúl 3.0
+//! 4/6: <@481280459058184204>
+//! X/6: @aaron_
+//! ```
+//!
+//! Players are mentioned when the app can, and written as `@name` otherwise,
+//! which the guild's aliases map back to users.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::DEFAULT_SCORE;
+use crate::storage::WordleAlias;
+
+static STREAK_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)your group is on an? (\d+) day streak").unwrap());
+static SCORE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b([1-6Xx])/6\s*:").unwrap());
+static MENTION_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"<@!?(\d+)>").unwrap());
+// Discord escapes markdown in names, e.g. @aaron\_
+static ESCAPE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\([[:punct:]])").unwrap());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Score {
+    /// Solved in this many guesses.
+    Solved(u32),
+    Failed,
+}
+
+impl Score {
+    /// The number used for rankings, where a failed game counts as
+    /// `DEFAULT_SCORE` guesses.
+    pub fn value(&self) -> u32 {
+        match self {
+            Score::Solved(guesses) => *guesses,
+            Score::Failed => DEFAULT_SCORE,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlayerResult {
+    pub user_id: u64,
+    pub score: Score,
+}
+
+/// One day's results, as read from a summary message.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DailyResult {
+    /// The streak the message reports, 1 on the group's first day.
+    pub streak: u32,
+    /// Results in the order they were listed. A player listed twice keeps the
+    /// first result.
+    pub results: Vec<PlayerResult>,
+    /// Names that matched no alias, e.g. "@someone new".
+    pub unresolved: Vec<String>,
+}
+
+impl DailyResult {
+    /// Score values by user id.
+    pub fn score_map(&self) -> HashMap<u64, u32> {
+        self.results
+            .iter()
+            .map(|r| (r.user_id, r.score.value()))
+            .collect()
+    }
+
+    fn add(&mut self, user_id: u64, score: Score) {
+        if !self.results.iter().any(|r| r.user_id == user_id) {
+            self.results.push(PlayerResult { user_id, score });
+        }
+    }
+}
+
+/// Parses a summary message, or returns `None` if `content` is not one.
+pub fn parse(content: &str, aliases: &[WordleAlias]) -> Option<DailyResult> {
+    let streak = STREAK_RE.captures(content)?.get(1)?.as_str().parse().ok()?;
+    let mut daily_result = DailyResult {
+        streak,
+        ..Default::default()
+    };
+
+    // Longest first, so an alias that is a prefix of another never wins
+    let mut aliases: Vec<&WordleAlias> = aliases.iter().collect();
+    aliases.sort_by_key(|alias| Reverse(alias.name.len()));
+
+    let mut score = None;
+    for line in content.lines() {
+        let line = ESCAPE_RE.replace_all(line, "$1");
+        let players = match SCORE_RE.captures(&line) {
+            Some(captures) => {
+                let score_match = captures.get(1).unwrap();
+                score = Some(match score_match.as_str() {
+                    "X" | "x" => Score::Failed,
+                    guesses => Score::Solved(guesses.parse().unwrap()),
+                });
+                &line[captures.get(0).unwrap().end()..]
+            }
+            // A long list of players can wrap onto the next lines
+            None if score.is_some() => &line[..],
+            None => continue,
+        };
+        let Some(score) = score else {
+            continue;
+        };
+        read_players(players, score, &aliases, &mut daily_result);
+    }
+
+    Some(daily_result)
+}
+
+/// Adds every player in `text` with `score`.
+fn read_players(text: &str, score: Score, aliases: &[&WordleAlias], result: &mut DailyResult) {
+    let mut rest = text.to_string();
+
+    for captures in MENTION_RE.captures_iter(text) {
+        if let Ok(user_id) = captures[1].parse() {
+            result.add(user_id, score);
+        }
+        rest = rest.replacen(&captures[0], " ", 1);
+    }
+
+    for alias in aliases {
+        while let Some(start) = find_name(&rest, &alias.name) {
+            result.add(alias.user_id, score);
+            rest.replace_range(start..start + alias.name.len(), " ");
+        }
+    }
+
+    for name in rest.split('@').skip(1) {
+        let name = name.trim();
+        if !name.is_empty() {
+            result.unresolved.push(format!("@{}", name));
+        }
+    }
+}
+
+/// Finds `name` in `text` where it is not just the start of a longer name.
+fn find_name(text: &str, name: &str) -> Option<usize> {
+    text.match_indices(name).map(|(i, _)| i).find(|&i| {
+        text[i + name.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| !c.is_alphanumeric() && c != '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases() -> Vec<WordleAlias> {
+        [
+            ("@rmanky", 150725833957441536),
+            ("@Raúl 3.0", 302973340371517441),
+            ("@troyotter", 481280459058184204),
+            ("@aaron_", 656347629524877312),
+            ("@dr: who", 111111111111111111),
+            ("@dr", 222222222222222222),
+        ]
+        .into_iter()
+        .map(|(name, user_id)| WordleAlias {
+            name: name.to_string(),
+            user_id,
+        })
+        .collect()
+    }
+
+    fn parse_fixture(content: &str) -> DailyResult {
+        parse(content, &aliases()).expect("fixture is a results message")
+    }
+
+    fn score_of(result: &DailyResult, user_id: u64) -> Option<Score> {
+        result
+            .results
+            .iter()
+            .find(|r| r.user_id == user_id)
+            .map(|r| r.score)
+    }
+
+    #[test]
+    fn parses_mentions_and_aliases() {
+        let result = parse_fixture(include_str!("fixtures/mentions_and_aliases.txt"));
+        assert_eq!(result.streak, 12);
+        assert_eq!(
+            score_of(&result, 150725833957441536),
+            Some(Score::Solved(3))
+        );
+        assert_eq!(
+            score_of(&result, 302973340371517441),
+            Some(Score::Solved(3))
+        );
+        assert_eq!(
+            score_of(&result, 481280459058184204),
+            Some(Score::Solved(4))
+        );
+        assert_eq!(score_of(&result, 656347629524877312), Some(Score::Failed));
+        assert_eq!(result.results.len(), 4);
+        assert!(result.unresolved.is_empty());
+    }
+
+    #[test]
+    fn reads_the_first_day_streak() {
+        let result = parse_fixture(include_str!("fixtures/first_day.txt"));
+        assert_eq!(result.streak, 1);
+        assert_eq!(result.results.len(), 2);
+    }
+
+    #[test]
+    fn failed_games_count_as_the_default_score() {
+        let result = parse_fixture(include_str!("fixtures/mentions_and_aliases.txt"));
+        assert_eq!(result.score_map()[&656347629524877312], DEFAULT_SCORE);
+    }
+
+    #[test]
+    fn ignores_crowns_and_emoji_before_the_score() {
+        let result = parse_fixture(include_str!("fixtures/crowns.txt"));
+        assert_eq!(
+            score_of(&result, 150725833957441536),
+            Some(Score::Solved(2))
+        );
+        assert_eq!(
+            score_of(&result, 481280459058184204),
+            Some(Score::Solved(2))
+        );
+        assert_eq!(
+            score_of(&result, 302973340371517441),
+            Some(Score::Solved(5))
+        );
+    }
+
+    #[test]
+    fn matches_names_containing_colons() {
+        let result = parse_fixture(include_str!("fixtures/colon_nickname.txt"));
+        assert_eq!(
+            score_of(&result, 111111111111111111),
+            Some(Score::Solved(4))
+        );
+        assert_eq!(
+            score_of(&result, 222222222222222222),
+            Some(Score::Solved(5))
+        );
+        assert!(result.unresolved.is_empty());
+    }
+
+    #[test]
+    fn continues_player_lists_onto_following_lines() {
+        let result = parse_fixture(include_str!("fixtures/wrapped_list.txt"));
+        for user_id in [150725833957441536, 302973340371517441, 481280459058184204] {
+            assert_eq!(score_of(&result, user_id), Some(Score::Solved(4)));
+        }
+        assert_eq!(score_of(&result, 656347629524877312), Some(Score::Failed));
+    }
+
+    #[test]
+    fn unescapes_markdown_in_names() {
+        let result = parse_fixture(include_str!("fixtures/escaped_names.txt"));
+        assert_eq!(
+            score_of(&result, 656347629524877312),
+            Some(Score::Solved(6))
+        );
+    }
+
+    #[test]
+    fn reports_names_without_an_alias() {
+        let result = parse_fixture(include_str!("fixtures/unknown_names.txt"));
+        assert_eq!(
+            result.unresolved,
+            vec!["@someone new".to_string(), "@rmanky2".to_string()]
+        );
+        assert_eq!(
+            score_of(&result, 481280459058184204),
+            Some(Score::Solved(3))
+        );
+        assert_eq!(score_of(&result, 150725833957441536), None);
+    }
+
+    #[test]
+    fn keeps_the_first_result_of_a_player_listed_twice() {
+        let result = parse(
+            "Your group is on a 3 day streak!\n2/6: <@1>\n5/6: <@1>",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(result.results.len(), 1);
+        assert_eq!(score_of(&result, 1), Some(Score::Solved(2)));
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        assert_eq!(
+            parse(include_str!("fixtures/not_results.txt"), &aliases()),
+            None
+        );
+    }
+}
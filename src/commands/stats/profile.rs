@@ -0,0 +1,345 @@
+//! One player's record across every tracked day.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BTreeMap;
+
+use twilight_model::http::attachment::Attachment;
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::embed::{EmbedBuilder, EmbedFieldBuilder, ImageSource};
+
+use super::chart::{distribution_chart, encode_png};
+use super::ranking::{format_date, week_start, RankedDay};
+use super::{load_days, PlayerCommand, DEFAULT_SCORE};
+use crate::commands::CommandHandlerData;
+use crate::utils::embed;
+
+/// Opponents shown in the head-to-head field.
+const MAX_OPPONENTS: usize = 10;
+const CHART_FILENAME: &str = "distribution.png";
+
+/// A week's games, keyed by the Monday it starts on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct Week {
+    pub start: i64,
+    pub days_played: usize,
+    pub average_score: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct HeadToHead {
+    pub wins: usize,
+    pub losses: usize,
+    pub ties: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct PlayerProfile {
+    /// Games by score, where the last entry counts failed games.
+    pub distribution: [usize; DEFAULT_SCORE as usize],
+    pub days_played: usize,
+    /// Consecutive days played, up to the latest tracked day.
+    pub current_streak: usize,
+    pub longest_streak: usize,
+    pub best_week: Option<Week>,
+    pub worst_week: Option<Week>,
+    /// Record against each opponent on days both played, by user id.
+    pub head_to_head: BTreeMap<u64, HeadToHead>,
+}
+
+impl PlayerProfile {
+    /// Builds `user_id`'s profile from `days`, oldest first, or returns
+    /// `None` if they never played.
+    pub(super) fn new(days: &[RankedDay], user_id: u64) -> Option<Self> {
+        let mut profile = PlayerProfile {
+            distribution: [0; DEFAULT_SCORE as usize],
+            days_played: 0,
+            current_streak: 0,
+            longest_streak: 0,
+            best_week: None,
+            worst_week: None,
+            head_to_head: BTreeMap::new(),
+        };
+        let mut weeks: BTreeMap<i64, (usize, u32)> = BTreeMap::new();
+        let mut streak = 0;
+        let mut last_played = None;
+
+        for day in days {
+            let Some(&score) = day.scores.get(&user_id) else {
+                continue;
+            };
+            profile.days_played += 1;
+            // A second results post for the same date doesn't extend the streak
+            if last_played != Some(day.date) {
+                streak = match last_played == Some(day.date - 1) {
+                    true => streak + 1,
+                    false => 1,
+                };
+                profile.longest_streak = profile.longest_streak.max(streak);
+                last_played = Some(day.date);
+            }
+            let bucket = score.clamp(1, DEFAULT_SCORE) as usize - 1;
+            profile.distribution[bucket] += 1;
+
+            let week = weeks.entry(week_start(day.date)).or_default();
+            week.0 += 1;
+            week.1 += score;
+
+            for (&opponent, &opponent_score) in &day.scores {
+                if opponent == user_id {
+                    continue;
+                }
+                let record = profile.head_to_head.entry(opponent).or_default();
+                match score.cmp(&opponent_score) {
+                    Ordering::Less => record.wins += 1,
+                    Ordering::Equal => record.ties += 1,
+                    Ordering::Greater => record.losses += 1,
+                }
+            }
+        }
+
+        if profile.days_played == 0 {
+            return None;
+        }
+        if last_played == days.iter().map(|day| day.date).max() {
+            profile.current_streak = streak;
+        }
+
+        let weeks: Vec<Week> = weeks
+            .into_iter()
+            .map(|(start, (days_played, total))| Week {
+                start,
+                days_played,
+                average_score: total as f64 / days_played as f64,
+            })
+            .collect();
+        // Earliest week first when averages tie
+        profile.best_week = weeks.iter().copied().reduce(|best, week| {
+            match week.average_score < best.average_score {
+                true => week,
+                false => best,
+            }
+        });
+        profile.worst_week = weeks.iter().copied().reduce(|worst, week| {
+            match week.average_score > worst.average_score {
+                true => week,
+                false => worst,
+            }
+        });
+
+        Some(profile)
+    }
+
+    pub(super) fn fails(&self) -> usize {
+        self.distribution[DEFAULT_SCORE as usize - 1]
+    }
+}
+
+pub(super) async fn player(
+    command_handler_data: &CommandHandlerData<'_>,
+    guild_id: u64,
+    command: &PlayerCommand,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &str,
+) {
+    let Some(user_id) = command.user.or(command_handler_data.user_id).map(Id::get) else {
+        return;
+    };
+    let Some(days) = load_days(
+        command_handler_data,
+        guild_id,
+        interaction_id,
+        interaction_token,
+    )
+    .await
+    else {
+        return;
+    };
+
+    let mut attachments = Vec::new();
+    let profile_embed = match PlayerProfile::new(&days, user_id) {
+        Some(profile) => {
+            let mut profile_embed = profile_embed(user_id, &profile);
+            match encode_png(&distribution_chart(&profile.distribution)) {
+                Ok(png) => {
+                    profile_embed =
+                        profile_embed.image(ImageSource::attachment(CHART_FILENAME).unwrap());
+                    attachments.push(Attachment::from_bytes(CHART_FILENAME.to_string(), png, 1));
+                }
+                Err(e) => log::warn!("Failed to render the guess distribution: {}", e),
+            }
+            profile_embed.build()
+        }
+        None => embed::info()
+            .title("Wordle profile")
+            .description(format!("<@{}> has not played yet.", user_id))
+            .build(),
+    };
+
+    command_handler_data
+        .interaction_client
+        .update_response(interaction_token)
+        .embeds(Some(&[profile_embed]))
+        .attachments(&attachments)
+        .await
+        .ok();
+}
+
+fn profile_embed(user_id: u64, profile: &PlayerProfile) -> EmbedBuilder {
+    let week = |week: Option<Week>| match week {
+        Some(week) => format!(
+            "Week of {}: **{:.2}** ({} days)",
+            format_date(week.start),
+            week.average_score,
+            week.days_played
+        ),
+        None => "None".to_string(),
+    };
+
+    let mut opponents: Vec<(&u64, &HeadToHead)> = profile.head_to_head.iter().collect();
+    // Most games together first, then by user id so the order is stable
+    opponents.sort_by_key(|(user_id, record)| {
+        (
+            Reverse(record.wins + record.losses + record.ties),
+            **user_id,
+        )
+    });
+    let head_to_head = match opponents.is_empty() {
+        true => "Nobody else played on the same days.".to_string(),
+        false => opponents
+            .iter()
+            .take(MAX_OPPONENTS)
+            .map(|(user_id, record)| {
+                format!(
+                    "<@{}> {}W {}L {}T",
+                    user_id, record.wins, record.losses, record.ties
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    embed::info()
+        .title("Wordle profile")
+        .description(format!(
+            "<@{}> has played {} days and failed {}.",
+            user_id,
+            profile.days_played,
+            profile.fails()
+        ))
+        .field(
+            EmbedFieldBuilder::new("Current streak", profile.current_streak.to_string()).inline(),
+        )
+        .field(
+            EmbedFieldBuilder::new("Longest streak", profile.longest_streak.to_string()).inline(),
+        )
+        .field(EmbedFieldBuilder::new("Best week", week(profile.best_week)))
+        .field(EmbedFieldBuilder::new(
+            "Worst week",
+            week(profile.worst_week),
+        ))
+        .field(EmbedFieldBuilder::new("Head to head", head_to_head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::stats::ranking::days_from_civil;
+    use crate::commands::stats::ranking::tests::day;
+
+    #[test]
+    fn counts_guesses_and_fails() {
+        let days = [
+            day(0, &[(1, 3)]),
+            day(1, &[(1, 3)]),
+            day(2, &[(1, DEFAULT_SCORE)]),
+        ];
+        let profile = PlayerProfile::new(&days, 1).unwrap();
+        assert_eq!(profile.distribution, [0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(profile.fails(), 1);
+        assert_eq!(profile.days_played, 3);
+    }
+
+    #[test]
+    fn tracks_current_and_longest_streaks() {
+        let days = [
+            day(0, &[(1, 3)]),
+            day(1, &[(1, 3)]),
+            day(2, &[(1, 3)]),
+            day(3, &[(2, 3)]),
+            day(4, &[(1, 4)]),
+        ];
+        let profile = PlayerProfile::new(&days, 1).unwrap();
+        assert_eq!(profile.longest_streak, 3);
+        assert_eq!(profile.current_streak, 1);
+    }
+
+    #[test]
+    fn breaks_streaks_on_missing_dates() {
+        let days = [
+            day(0, &[(1, 3)]),
+            day(1, &[(1, 3)]),
+            day(3, &[(1, 3)]),
+            day(4, &[(1, 3)]),
+        ];
+        let profile = PlayerProfile::new(&days, 1).unwrap();
+        assert_eq!(profile.longest_streak, 2);
+        assert_eq!(profile.current_streak, 2);
+
+        let profile = PlayerProfile::new(&[day(0, &[(1, 3)]), day(2, &[(2, 3)])], 1).unwrap();
+        assert_eq!(profile.current_streak, 0);
+    }
+
+    #[test]
+    fn counts_a_date_once_in_streaks() {
+        let days = [
+            day(0, &[(1, 3)]),
+            day(1, &[(1, 3)]),
+            day(1, &[(1, 4)]),
+            day(2, &[(1, 3)]),
+        ];
+        let profile = PlayerProfile::new(&days, 1).unwrap();
+        assert_eq!(profile.longest_streak, 3);
+        assert_eq!(profile.current_streak, 3);
+    }
+
+    #[test]
+    fn finds_best_and_worst_weeks() {
+        let monday = days_from_civil(2026, 10, 5);
+        let days = [
+            day(monday, &[(1, 5)]),
+            day(monday + 1, &[(1, 3)]),
+            day(monday + 7, &[(1, 2)]),
+            day(monday + 14, &[(1, 6)]),
+        ];
+        let profile = PlayerProfile::new(&days, 1).unwrap();
+        assert_eq!(profile.best_week.unwrap().start, monday + 7);
+        let worst = profile.worst_week.unwrap();
+        assert_eq!(worst.start, monday + 14);
+        assert_eq!(worst.average_score, 6.0);
+    }
+
+    #[test]
+    fn records_head_to_head() {
+        let days = [
+            day(0, &[(1, 3), (2, 4)]),
+            day(1, &[(1, 4), (2, 4)]),
+            day(2, &[(1, 5), (2, 2)]),
+            day(3, &[(2, 2)]),
+        ];
+        let profile = PlayerProfile::new(&days, 1).unwrap();
+        assert_eq!(
+            profile.head_to_head[&2],
+            HeadToHead {
+                wins: 1,
+                losses: 1,
+                ties: 1
+            }
+        );
+    }
+
+    #[test]
+    fn has_no_profile_without_games() {
+        assert_eq!(PlayerProfile::new(&[day(0, &[(2, 3)])], 1), None);
+    }
+}
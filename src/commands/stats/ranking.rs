@@ -0,0 +1,380 @@
+//! Ranking players over a window of days.
+//!
+//! Days are dated by UTC, and a results message counts for the day before it
+//! was posted, since it reports "yesterday's results".
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+use twilight_interactions::command::{CommandOption, CreateOption};
+
+use super::DEFAULT_SCORE;
+
+/// Discord's epoch, in milliseconds since the Unix epoch.
+const DISCORD_EPOCH_MS: u64 = 1_420_070_400_000;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+/// Days covered by "last N days" when no number is given.
+pub(super) const DEFAULT_WINDOW_DAYS: u32 = 30;
+
+const INITIAL_RATING: f64 = 1500.0;
+/// The most a rating can move in one day.
+const RATING_K: f64 = 32.0;
+
+#[derive(CommandOption, CreateOption, Clone, Copy)]
+pub(super) enum LeaderboardPeriod {
+    #[option(name = "This week", value = "week")]
+    ThisWeek,
+    #[option(name = "This month", value = "month")]
+    ThisMonth,
+    #[option(name = "Last N days", value = "days")]
+    LastDays,
+    #[option(name = "All time", value = "all")]
+    AllTime,
+}
+
+#[derive(CommandOption, CreateOption, Clone, Copy)]
+pub(super) enum RankingMode {
+    #[option(name = "Average score", value = "average")]
+    Average,
+    #[option(name = "Total with missed days", value = "total")]
+    Total,
+    #[option(name = "Wins", value = "wins")]
+    Wins,
+    #[option(name = "Rating", value = "rating")]
+    Rating,
+}
+
+impl RankingMode {
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            RankingMode::Average => "average score",
+            RankingMode::Total => "total with missed days",
+            RankingMode::Wins => "wins",
+            RankingMode::Rating => "rating",
+        }
+    }
+}
+
+/// The days to rank over, as a resolved start date.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Window {
+    /// The first day included, in days since the Unix epoch, or `None` for
+    /// all time.
+    pub start: Option<i64>,
+}
+
+impl Window {
+    /// Resolves `period` relative to `today`, in days since the Unix epoch.
+    /// `days` is only used by `LastDays`.
+    pub(super) fn new(period: LeaderboardPeriod, days: u32, today: i64) -> Self {
+        let start = match period {
+            LeaderboardPeriod::ThisWeek => Some(week_start(today)),
+            LeaderboardPeriod::ThisMonth => {
+                let (year, month, _) = civil_from_days(today);
+                Some(days_from_civil(year, month, 1))
+            }
+            LeaderboardPeriod::LastDays => Some(today - days as i64),
+            LeaderboardPeriod::AllTime => None,
+        };
+        Window { start }
+    }
+
+    pub(super) fn contains(&self, date: i64) -> bool {
+        self.start.is_none_or(|start| date >= start)
+    }
+}
+
+/// The day a results message reports on, in days since the Unix epoch.
+pub(super) fn game_date(message_id: u64) -> i64 {
+    let posted_ms = (message_id >> 22) + DISCORD_EPOCH_MS;
+    (posted_ms / 1000 / SECS_PER_DAY) as i64 - 1
+}
+
+/// Today, in days since the Unix epoch.
+pub(super) fn today(unix_secs: u64) -> i64 {
+    (unix_secs / SECS_PER_DAY) as i64
+}
+
+/// The Monday on or before `date`.
+pub(super) fn week_start(date: i64) -> i64 {
+    // 1970-01-01 was a Thursday, so Monday is 3 days before it
+    date - (date + 3).rem_euclid(7)
+}
+
+/// Formats `date` as YYYY-MM-DD.
+pub(super) fn format_date(date: i64) -> String {
+    let (year, month, day) = civil_from_days(date);
+    format!("{}-{:02}-{:02}", year, month, day)
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date.
+pub(super) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm, with years starting on the 1st of March
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date to days since the Unix epoch.
+pub(super) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = (month as i64 + 9) % 12;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// One day's scores by user id.
+pub(super) struct RankedDay {
+    pub date: i64,
+    pub scores: HashMap<u64, u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct PlayerStats {
+    pub user_id: u64,
+    /// Played scores, plus `DEFAULT_SCORE` for each missed day in the window.
+    pub penalized_score: u32,
+    pub average_score: f64,
+    pub days_played: usize,
+    /// Days with the best score of everyone who played. Nobody wins a day
+    /// that everyone failed.
+    pub wins: usize,
+    pub rating: f64,
+}
+
+/// Ranks everyone who played in `window`, best first. Ties are broken by
+/// average score, then days played, then user id, so the order is the same
+/// on every call.
+pub(super) fn rank(days: &[RankedDay], window: Window, mode: RankingMode) -> Vec<PlayerStats> {
+    let days: Vec<&RankedDay> = days.iter().filter(|d| window.contains(d.date)).collect();
+
+    let mut players: BTreeMap<u64, PlayerStats> = BTreeMap::new();
+    for day in &days {
+        for &user_id in day.scores.keys() {
+            players.entry(user_id).or_insert(PlayerStats {
+                user_id,
+                penalized_score: 0,
+                average_score: 0.0,
+                days_played: 0,
+                wins: 0,
+                rating: INITIAL_RATING,
+            });
+        }
+    }
+
+    let mut totals: HashMap<u64, u32> = HashMap::new();
+    for day in &days {
+        let best = day
+            .scores
+            .values()
+            .copied()
+            .min()
+            .filter(|&best| best < DEFAULT_SCORE);
+        for stats in players.values_mut() {
+            match day.scores.get(&stats.user_id) {
+                Some(&score) => {
+                    *totals.entry(stats.user_id).or_default() += score;
+                    stats.penalized_score += score;
+                    stats.days_played += 1;
+                    if Some(score) == best {
+                        stats.wins += 1;
+                    }
+                }
+                None => stats.penalized_score += DEFAULT_SCORE,
+            }
+        }
+        update_ratings(&mut players, &day.scores);
+    }
+
+    let mut ranking: Vec<PlayerStats> = players
+        .into_values()
+        .map(|mut stats| {
+            if stats.days_played > 0 {
+                stats.average_score = totals[&stats.user_id] as f64 / stats.days_played as f64;
+            }
+            stats
+        })
+        .collect();
+
+    ranking.sort_by(|a, b| {
+        compare(a, b, mode)
+            .then_with(|| a.average_score.total_cmp(&b.average_score))
+            .then_with(|| b.days_played.cmp(&a.days_played))
+            .then_with(|| a.user_id.cmp(&b.user_id))
+    });
+    ranking
+}
+
+/// Orders two players by `mode` alone, better first.
+fn compare(a: &PlayerStats, b: &PlayerStats, mode: RankingMode) -> Ordering {
+    match mode {
+        RankingMode::Average => a.average_score.total_cmp(&b.average_score),
+        RankingMode::Total => a.penalized_score.cmp(&b.penalized_score),
+        RankingMode::Wins => b.wins.cmp(&a.wins),
+        RankingMode::Rating => b.rating.total_cmp(&a.rating),
+    }
+}
+
+/// Elo-style update, treating a day as a game between every pair of players
+/// who played it. Ratings change together at the end of the day, so the
+/// order players are visited in does not matter.
+fn update_ratings(players: &mut BTreeMap<u64, PlayerStats>, scores: &HashMap<u64, u32>) {
+    if scores.len() < 2 {
+        return;
+    }
+    let opponents = (scores.len() - 1) as f64;
+    let changes: Vec<(u64, f64)> = players
+        .values()
+        .filter_map(|player| {
+            let score = scores.get(&player.user_id)?;
+            let change: f64 = players
+                .values()
+                .filter(|other| other.user_id != player.user_id)
+                .filter_map(|other| {
+                    let other_score = scores.get(&other.user_id)?;
+                    let actual = match score.cmp(other_score) {
+                        Ordering::Less => 1.0,
+                        Ordering::Equal => 0.5,
+                        Ordering::Greater => 0.0,
+                    };
+                    let expected = 1.0 / (1.0 + 10f64.powf((other.rating - player.rating) / 400.0));
+                    Some(actual - expected)
+                })
+                .sum();
+            Some((player.user_id, RATING_K * change / opponents))
+        })
+        .collect();
+
+    for (user_id, change) in changes {
+        if let Some(player) = players.get_mut(&user_id) {
+            player.rating += change;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub(in crate::commands::stats) fn day(date: i64, scores: &[(u64, u32)]) -> RankedDay {
+        RankedDay {
+            date,
+            scores: scores.iter().copied().collect(),
+        }
+    }
+
+    fn order(ranking: &[PlayerStats]) -> Vec<u64> {
+        ranking.iter().map(|s| s.user_id).collect()
+    }
+
+    const ALL_TIME: Window = Window { start: None };
+
+    #[test]
+    fn converts_civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in [-800_000, -1, 0, 59, 10_956, 20_000, 800_000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn resolves_periods() {
+        // Wednesday 2026-10-14
+        let today = days_from_civil(2026, 10, 14);
+        assert_eq!(
+            Window::new(LeaderboardPeriod::ThisWeek, 0, today).start,
+            Some(days_from_civil(2026, 10, 12))
+        );
+        assert_eq!(
+            Window::new(LeaderboardPeriod::ThisMonth, 0, today).start,
+            Some(days_from_civil(2026, 10, 1))
+        );
+        assert_eq!(
+            Window::new(LeaderboardPeriod::LastDays, 7, today).start,
+            Some(today - 7)
+        );
+        assert_eq!(
+            Window::new(LeaderboardPeriod::AllTime, 7, today).start,
+            None
+        );
+    }
+
+    #[test]
+    fn dates_results_by_the_day_before_posting() {
+        // Posted 2024-01-02T00:00:00Z
+        let posted_ms = days_from_civil(2024, 1, 2) as u64 * SECS_PER_DAY * 1000;
+        let message_id = (posted_ms - DISCORD_EPOCH_MS) << 22;
+        assert_eq!(game_date(message_id), days_from_civil(2024, 1, 1));
+    }
+
+    #[test]
+    fn penalizes_missed_days_in_the_total() {
+        let days = [day(0, &[(1, 3), (2, 4)]), day(1, &[(2, 4)])];
+        let ranking = rank(&days, ALL_TIME, RankingMode::Total);
+        assert_eq!(order(&ranking), vec![2, 1]);
+        assert_eq!(ranking[1].penalized_score, 3 + DEFAULT_SCORE);
+        assert_eq!(ranking[1].average_score, 3.0);
+    }
+
+    #[test]
+    fn only_counts_days_in_the_window() {
+        let days = [day(0, &[(1, 6)]), day(5, &[(2, 2)])];
+        let ranking = rank(&days, Window { start: Some(5) }, RankingMode::Average);
+        assert_eq!(order(&ranking), vec![2]);
+        assert_eq!(ranking[0].penalized_score, 2);
+    }
+
+    #[test]
+    fn counts_shared_wins_but_not_all_failed_days() {
+        let days = [
+            day(0, &[(1, 3), (2, 3), (3, 5)]),
+            day(1, &[(1, 4), (3, 2)]),
+            day(2, &[(1, DEFAULT_SCORE), (2, DEFAULT_SCORE)]),
+        ];
+        let ranking = rank(&days, ALL_TIME, RankingMode::Wins);
+        let wins: Vec<(u64, usize)> = ranking.iter().map(|s| (s.user_id, s.wins)).collect();
+        // Everyone has one win, so the better average goes first
+        assert_eq!(wins, vec![(3, 1), (1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn rates_players_who_beat_others_higher() {
+        let days = [day(0, &[(1, 2), (2, 4), (3, 6)]), day(1, &[(1, 3), (2, 4)])];
+        let ranking = rank(&days, ALL_TIME, RankingMode::Rating);
+        assert_eq!(order(&ranking), vec![1, 2, 3]);
+        assert!(ranking[0].rating > INITIAL_RATING);
+        assert!(ranking[2].rating < INITIAL_RATING);
+    }
+
+    #[test]
+    fn breaks_exact_ties_by_user_id() {
+        let days = [day(0, &[(9, 4), (3, 4), (5, 4)])];
+        for mode in [
+            RankingMode::Average,
+            RankingMode::Total,
+            RankingMode::Wins,
+            RankingMode::Rating,
+        ] {
+            assert_eq!(order(&rank(&days, ALL_TIME, mode)), vec![3, 5, 9]);
+        }
+    }
+}
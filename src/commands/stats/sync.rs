@@ -0,0 +1,194 @@
+//! Reading results messages from the configured channel into storage.
+
+use std::time::Duration;
+
+use twilight_http::error::ErrorType;
+use twilight_model::channel::Message;
+use twilight_model::id::marker::{ChannelMarker, MessageMarker};
+use twilight_model::id::Id;
+
+use super::parser::{self, DailyResult};
+use crate::commands::CommandHandlerData;
+use crate::storage::{Storage, StorageError, WordleConfig, WordleDay};
+
+enum Page {
+    Latest,
+    Before(Id<MessageMarker>),
+    After(Id<MessageMarker>),
+}
+
+/// Fetches up to 100 messages, newest first, waiting out rate limits.
+async fn fetch_page(
+    data: &CommandHandlerData<'_>,
+    channel_id: Id<ChannelMarker>,
+    page: &Page,
+) -> Result<Vec<Message>, Box<dyn std::error::Error + Send + Sync>> {
+    loop {
+        let request = data.twilight_client.channel_messages(channel_id);
+        let result = match *page {
+            Page::Latest => request.limit(100).await,
+            Page::Before(message_id) => request.before(message_id).limit(100).await,
+            Page::After(message_id) => request.after(message_id).limit(100).await,
+        };
+
+        match result {
+            Ok(response) => return Ok(response.model().await?),
+            Err(e) => {
+                if let ErrorType::Response { body, status, .. } = e.kind() {
+                    if *status == 429 {
+                        let body_json: serde_json::Value = serde_json::from_slice(body)?;
+                        let retry_after = body_json["retry_after"].as_f64().unwrap_or(0.5);
+                        log::warn!("Rate limited, retrying after {}s", retry_after);
+                        tokio::time::sleep(Duration::from_secs_f64(retry_after)).await;
+                        continue;
+                    }
+                }
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+/// The results in `message`, if it is a summary from the results bot.
+fn read_results(message: &Message, config: &WordleConfig) -> Option<DailyResult> {
+    if message.author.id.get() != config.bot_id {
+        return None;
+    }
+    parser::parse(&message.content, &config.aliases)
+}
+
+fn wordle_day(message: &Message, result: &DailyResult) -> WordleDay {
+    if !result.unresolved.is_empty() {
+        log::warn!(
+            "Wordle results message {} has names without an alias: {}",
+            message.id,
+            result.unresolved.join(", ")
+        );
+    }
+    WordleDay {
+        message_id: message.id.get(),
+        content: message.content.clone(),
+        scores: result.score_map(),
+    }
+}
+
+/// Reads the results posted since the channel was last read. The first time,
+/// the channel is read back to the message that started the streak.
+pub(super) async fn sync_channel(
+    data: &CommandHandlerData<'_>,
+    guild_id: u64,
+    config: &WordleConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let channel_id = Id::<ChannelMarker>::new(config.channel_id);
+    let mut results = Vec::new();
+    let mut newest_seen: Option<Id<MessageMarker>> = None;
+    let mut num_messages_crawled = 0;
+
+    match config.last_message_id {
+        Some(last_message_id) => {
+            let mut after = Id::new(last_message_id);
+            loop {
+                let messages = fetch_page(data, channel_id, &Page::After(after)).await?;
+                num_messages_crawled += messages.len();
+                let Some(newest) = messages.iter().map(|m| m.id).max() else {
+                    break;
+                };
+                after = newest;
+                newest_seen = Some(newest);
+
+                let full_page = messages.len() == 100;
+                results.extend(
+                    messages.iter().filter_map(|m| {
+                        read_results(m, config).map(|result| wordle_day(m, &result))
+                    }),
+                );
+                if !full_page {
+                    break;
+                }
+            }
+        }
+        None => {
+            let mut page = Page::Latest;
+            'outer: loop {
+                let messages = fetch_page(data, channel_id, &page).await?;
+                let Some(oldest) = messages.last().map(|m| m.id) else {
+                    break;
+                };
+                num_messages_crawled += messages.len();
+                newest_seen = newest_seen.or(messages.first().map(|m| m.id));
+                page = Page::Before(oldest);
+
+                for message in &messages {
+                    let Some(result) = read_results(message, config) else {
+                        continue;
+                    };
+                    results.push(wordle_day(message, &result));
+                    if result.streak == 1 {
+                        break 'outer;
+                    }
+                }
+            }
+        }
+    }
+
+    for day in &results {
+        data.storage.store_wordle_day(guild_id, day)?;
+    }
+    if let Some(newest) = newest_seen {
+        data.storage.advance_wordle_cursor(guild_id, newest.get())?;
+    }
+
+    log::info!("Stored {} new results messages.", results.len());
+    log::info!("Crawled {} messages.", num_messages_crawled);
+    Ok(())
+}
+
+/// Stores a results message as soon as it is posted, so the next leaderboard
+/// has less to read. The read position is left alone, since messages posted
+/// while the bot was offline still have to be read.
+pub fn ingest_message(storage: &Storage, message: &Message) {
+    let Some(guild_id) = message.guild_id else {
+        return;
+    };
+    // Every message in the guild passes through here, so only the results
+    // bot's messages in the tracked channel load the aliases
+    match storage.wordle_channel(guild_id.get()) {
+        Ok(Some((channel_id, bot_id)))
+            if message.channel_id.get() == channel_id && message.author.id.get() == bot_id => {}
+        Ok(_) => return,
+        Err(e) => {
+            log::warn!("Failed to load Wordle settings: {}", e);
+            return;
+        }
+    }
+    let config = match storage.wordle_config(guild_id.get()) {
+        Ok(Some(config)) => config,
+        Ok(None) => return,
+        Err(e) => {
+            log::warn!("Failed to load Wordle settings: {}", e);
+            return;
+        }
+    };
+    let Some(result) = read_results(message, &config) else {
+        return;
+    };
+
+    match storage.store_wordle_day(guild_id.get(), &wordle_day(message, &result)) {
+        Ok(()) => log::info!("Stored Wordle results message {}", message.id),
+        Err(e) => log::warn!("Failed to store Wordle results: {}", e),
+    }
+}
+
+/// Re-reads the stored results with the current aliases.
+pub(super) fn reparse_days(storage: &Storage, guild_id: u64) -> Result<(), StorageError> {
+    let Some(config) = storage.wordle_config(guild_id)? else {
+        return Ok(());
+    };
+    for mut day in storage.wordle_days(guild_id)? {
+        day.scores = parser::parse(&day.content, &config.aliases)
+            .map(|result| result.score_map())
+            .unwrap_or_default();
+        storage.store_wordle_day(guild_id, &day)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use twilight_interactions::command::{CommandModel, CommandOption, CreateCommand, CreateOption};
+use twilight_model::channel::message::MessageFlags;
+use twilight_model::guild::Permissions;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::embed::{EmbedBuilder, EmbedFieldBuilder, EmbedFooterBuilder};
+
+use super::{is_bot_owner, CommandHandler, CommandHandlerData};
+use crate::storage::{unix_now, LedgerGrouping, LedgerTotal, Storage, StorageError};
+use crate::utils::embed;
+
+const MAX_ROWS: usize = 10;
+
+fn administrator() -> Permissions {
+    Permissions::ADMINISTRATOR
+}
+
+#[derive(CommandOption, CreateOption, Clone, Copy)]
+enum UsagePeriod {
+    #[option(name = "Last 24 hours", value = "day")]
+    Day,
+    #[option(name = "Last 7 days", value = "week")]
+    Week,
+    #[option(name = "Last 30 days", value = "month")]
+    Month,
+}
+
+impl UsagePeriod {
+    fn name(&self) -> &'static str {
+        match self {
+            UsagePeriod::Day => "last 24 hours",
+            UsagePeriod::Week => "last 7 days",
+            UsagePeriod::Month => "last 30 days",
+        }
+    }
+
+    fn secs(&self) -> u64 {
+        let day = 24 * 60 * 60;
+        match self {
+            UsagePeriod::Day => day,
+            UsagePeriod::Week => 7 * day,
+            UsagePeriod::Month => 30 * day,
+        }
+    }
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(
+    name = "usage",
+    desc = "Show provider calls and their estimated cost",
+    default_permissions = "administrator",
+    dm_permission = false
+)]
+pub struct UsageCommand {
+    /// Period to total. Uses the last 24 hours by default.
+    period: Option<UsagePeriod>,
+    /// Include calls from every server, not just this one. Bot owner only.
+    everywhere: Option<bool>,
+}
+
+#[async_trait]
+impl CommandHandler for UsageCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str,
+    ) {
+        let Some(guild_id) = command_handler_data.guild_id else {
+            return;
+        };
+        let period = self.period.unwrap_or(UsagePeriod::Day);
+        let everywhere = self.everywhere.unwrap_or(false);
+
+        let usage_embed = if everywhere && !is_bot_owner(command_handler_data.user_id) {
+            embed::failure("Only the bot owner can view usage from every server.")
+        } else {
+            let scope = match everywhere {
+                true => None,
+                false => Some(guild_id.get()),
+            };
+            match usage_report(&command_handler_data.storage, period, scope) {
+                Ok(report) => report,
+                Err(e) => embed::failure(&e.to_string()),
+            }
+        };
+
+        command_handler_data
+            .interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        embeds: Some(vec![usage_embed.build()]),
+                        flags: Some(MessageFlags::EPHEMERAL),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await
+            .ok();
+    }
+}
+
+fn usage_report(
+    storage: &Storage,
+    period: UsagePeriod,
+    guild_id: Option<u64>,
+) -> Result<EmbedBuilder, StorageError> {
+    let since = unix_now().saturating_sub(period.secs());
+    let total = storage
+        .ledger_totals(LedgerGrouping::Total, since, guild_id, 1)?
+        .into_iter()
+        .next();
+    let models = storage.ledger_totals(LedgerGrouping::Model, since, guild_id, MAX_ROWS)?;
+    let users = storage.ledger_totals(LedgerGrouping::User, since, guild_id, MAX_ROWS)?;
+
+    let scope = match guild_id {
+        Some(_) => "this server",
+        None => "every server",
+    };
+    let mut usage_embed = embed::info().title(format!("Usage in {}, {}", scope, period.name()));
+
+    let Some(total) = total else {
+        return Ok(usage_embed.description("No provider calls were made."));
+    };
+    usage_embed = usage_embed.description(summary(&total));
+
+    let model_lines: Vec<String> = models
+        .iter()
+        .map(|m| format!("`{}` · {}", m.key, summary(m)))
+        .collect();
+    usage_embed = usage_embed.field(EmbedFieldBuilder::new("By model", model_lines.join("\n")));
+
+    let user_lines: Vec<String> = users
+        .iter()
+        .map(|u| {
+            let user = match u.key.parse::<u64>() {
+                Ok(id) => format!("<@{}>", id),
+                Err(_) => "Unknown user".to_string(),
+            };
+            format!("{} · {}", user, summary(u))
+        })
+        .collect();
+    usage_embed = usage_embed.field(EmbedFieldBuilder::new("By user", user_lines.join("\n")));
+
+    Ok(usage_embed.footer(EmbedFooterBuilder::new(
+        "Costs are estimates from the configured price table",
+    )))
+}
+
+fn summary(total: &LedgerTotal) -> String {
+    let mut parts = vec![match total.failures {
+        0 => format!("{} calls", total.calls),
+        failures => format!("{} calls ({} failed)", total.calls, failures),
+    }];
+    if total.images > 0 {
+        parts.push(format!("{} images", total.images));
+    }
+    let tokens = total.input_tokens + total.output_tokens;
+    if tokens > 0 {
+        parts.push(format!("{} tokens", format_count(tokens)));
+    }
+    parts.push(format!("${:.2}", total.cost));
+    parts.join(" · ")
+}
+
+fn format_count(count: u64) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=999_999 => format!("{:.1}k", count as f64 / 1_000.0),
+        _ => format!("{:.1}M", count as f64 / 1_000_000.0),
+    }
+}
@@ -0,0 +1,187 @@
+//! A local record of every provider call, with its estimated cost.
+//!
+//! Costs come from a price table keyed by model name prefix. Entries can be
+//! added or replaced with `LEDGER_PRICES`, a JSON object such as
+//! `{"imagen-4.0-generate": {"image": 0.04}, "qwen/": {"call": 0.002}}`.
+//! Prices are in US dollars, per call, per image, and per million input or
+//! output tokens. Calls on a free tier key and models without a price are
+//! recorded at no cost.
+
+use std::collections::HashMap;
+use std::env;
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::storage::{LedgerEntry, Storage};
+use crate::utils::google_ai::GOOGLE_API_FREE_KEY;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default)]
+struct Price {
+    call: f64,
+    image: f64,
+    input_mtok: f64,
+    output_mtok: f64,
+}
+
+const DEFAULT_PRICES: &[(&str, Price)] = &[
+    (
+        "imagen-4.0-generate",
+        Price {
+            call: 0.0,
+            image: 0.04,
+            input_mtok: 0.0,
+            output_mtok: 0.0,
+        },
+    ),
+    (
+        "imagen-4.0-fast-generate",
+        Price {
+            call: 0.0,
+            image: 0.02,
+            input_mtok: 0.0,
+            output_mtok: 0.0,
+        },
+    ),
+    (
+        "imagen-4.0-ultra-generate",
+        Price {
+            call: 0.0,
+            image: 0.06,
+            input_mtok: 0.0,
+            output_mtok: 0.0,
+        },
+    ),
+    // Gemini bills generated images as output tokens
+    (
+        "gemini-2.5-flash-image",
+        Price {
+            call: 0.0,
+            image: 0.0,
+            input_mtok: 0.30,
+            output_mtok: 30.0,
+        },
+    ),
+    (
+        "gemini-3-pro-image",
+        Price {
+            call: 0.0,
+            image: 0.0,
+            input_mtok: 2.0,
+            output_mtok: 120.0,
+        },
+    ),
+];
+
+static PRICES: Lazy<Vec<(String, Price)>> = Lazy::new(|| {
+    let mut prices: HashMap<String, Price> = DEFAULT_PRICES
+        .iter()
+        .map(|(prefix, price)| (prefix.to_string(), *price))
+        .collect();
+    if let Ok(overrides) = env::var("LEDGER_PRICES") {
+        match serde_json::from_str::<HashMap<String, Price>>(&overrides) {
+            Ok(overrides) => prices.extend(overrides),
+            Err(e) => log::warn!("Ignoring invalid LEDGER_PRICES: {}", e),
+        }
+    }
+
+    // Longest prefix first, so the most specific entry wins
+    let mut prices: Vec<(String, Price)> = prices.into_iter().collect();
+    prices.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    prices
+});
+
+fn price(model: &str) -> Option<Price> {
+    PRICES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix.as_str()))
+        .map(|(_, price)| *price)
+}
+
+/// What a finished call used. Anything the provider did not report is left
+/// at zero.
+#[derive(Debug, Default)]
+pub struct CallUsage {
+    pub success: bool,
+    /// The key tier that served the call, for providers that have tiers.
+    pub tier: Option<&'static str>,
+    pub images: u32,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl CallUsage {
+    pub fn failed() -> Self {
+        CallUsage::default()
+    }
+
+    fn estimated_cost(&self, model: &str) -> f64 {
+        if self.tier == Some(GOOGLE_API_FREE_KEY.tier) {
+            return 0.0;
+        }
+        let Some(price) = price(model) else {
+            return 0.0;
+        };
+        price.call
+            + price.image * self.images as f64
+            + price.input_mtok * self.input_tokens as f64 / 1_000_000.0
+            + price.output_mtok * self.output_tokens as f64 / 1_000_000.0
+    }
+}
+
+/// A provider call in progress, timed from when it was started.
+pub struct ProviderCall {
+    command: &'static str,
+    provider: &'static str,
+    model: String,
+    user_id: Option<u64>,
+    guild_id: Option<u64>,
+    started: Instant,
+}
+
+impl ProviderCall {
+    /// `provider` is the service being called, e.g. "google" or "horde".
+    pub fn start(
+        command: &'static str,
+        provider: &'static str,
+        model: &str,
+        user_id: Option<u64>,
+        guild_id: Option<u64>,
+    ) -> Self {
+        ProviderCall {
+            command,
+            provider,
+            model: model.to_string(),
+            user_id,
+            guild_id,
+            started: Instant::now(),
+        }
+    }
+
+    /// Records the call in the ledger. Failing to record it is only logged.
+    pub fn finish(self, storage: &Storage, usage: CallUsage) {
+        let entry = LedgerEntry {
+            command: self.command.to_string(),
+            user_id: self.user_id,
+            guild_id: self.guild_id,
+            provider: self.provider.to_string(),
+            cost: usage.estimated_cost(&self.model),
+            model: self.model,
+            tier: usage.tier.map(str::to_string),
+            images: usage.images,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            latency_ms: self.started.elapsed().as_millis() as u64,
+            success: usage.success,
+        };
+        if let Err(e) = storage.insert_ledger_entry(&entry) {
+            log::warn!(
+                "Failed to record {} call in the ledger: {}",
+                entry.command,
+                e
+            );
+        }
+    }
+}
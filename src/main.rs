@@ -3,6 +3,7 @@ use activity::get_random_activity;
 use commands::CommandDelegate;
 use dotenv::dotenv;
 use std::{env, error::Error, sync::Arc, time::Duration};
+use storage::Storage;
 use twilight_cache_inmemory::DefaultInMemoryCache;
 use twilight_gateway::{Event, EventTypeFlags, Intents, Shard, ShardId, StreamExt};
 use twilight_http::Client as HttpClient;
@@ -13,6 +14,9 @@ use twilight_model::{
 
 mod activity;
 mod commands;
+mod ledger;
+mod quota;
+mod storage;
 mod utils;
 
 #[tokio::main]
@@ -22,7 +26,8 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     let token = env::var("DISCORD_TOKEN")?;
 
-    let intents = Intents::GUILD_MESSAGES | Intents::DIRECT_MESSAGES;
+    // Message content is needed to read Wordle results as they are posted
+    let intents = Intents::GUILD_MESSAGES | Intents::DIRECT_MESSAGES | Intents::MESSAGE_CONTENT;
 
     let mut shard = Shard::new(ShardId::new(0, 1), token.clone(), intents);
     let sender = shard.sender();
@@ -46,6 +51,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let command_data = Arc::new(CommandDelegateData {
         reqwest_client: reqwest::Client::new(),
         twilight_client: HttpClient::new(token.clone()),
+        storage: Storage::open()?,
     });
 
     let application_id = command_data
@@ -62,6 +68,10 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .set_global_commands(&command_data.command_definitions())
         .await?;
 
+    // Pick up generations that were in flight when the bot last stopped.
+    let resume_data = Arc::clone(&command_data);
+    tokio::spawn(async move { resume_data.resume_jobs(application_id).await });
+
     let cache = DefaultInMemoryCache::builder()
         .message_cache_size(10)
         .build();
@@ -90,8 +100,10 @@ async fn handle_event(
     application_id: Id<ApplicationMarker>,
     command_data: Arc<CommandDelegateData>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    if let Event::InteractionCreate(i) = event {
-        command_data.handle_interaction(i.0, application_id).await;
+    match event {
+        Event::InteractionCreate(i) => command_data.handle_interaction(i.0, application_id).await,
+        Event::MessageCreate(m) => command_data.handle_message(m.0).await,
+        _ => {}
     }
 
     Ok(())
@@ -0,0 +1,256 @@
+//! Request quotas for the providers that cost money, checked before a
+//! request is sent.
+//!
+//! Every limit can be changed with an environment variable named
+//! `QUOTA_<PROVIDER>_<SCOPE>_<WINDOW>`, e.g. `QUOTA_IMAGEN_USER_HOUR=10`.
+//! Setting one to 0 removes that limit.
+
+use std::env;
+use std::sync::Mutex;
+
+use twilight_model::channel::message::Embed;
+use twilight_util::builder::embed::EmbedFieldBuilder;
+
+use crate::storage::{unix_now, Storage, UsageScope};
+use crate::utils::embed;
+
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * HOUR_SECS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    /// Gemini image generation, used by /nano.
+    Gemini,
+    /// Imagen, used by /dream. Only ever runs on the paid key.
+    Imagen,
+}
+
+impl Provider {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Gemini => "gemini",
+            Provider::Imagen => "imagen",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Scope {
+    User,
+    Guild,
+    Global,
+}
+
+impl Scope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scope::User => "USER",
+            Scope::Guild => "GUILD",
+            Scope::Global => "GLOBAL",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Window {
+    Hour,
+    Day,
+}
+
+impl Window {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Window::Hour => "HOUR",
+            Window::Day => "DAY",
+        }
+    }
+
+    fn secs(&self) -> u64 {
+        match self {
+            Window::Hour => HOUR_SECS,
+            Window::Day => DAY_SECS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Limit {
+    scope: Scope,
+    window: Window,
+    max: u32,
+}
+
+const DEFAULT_LIMITS: &[(Provider, Scope, Window, u32)] = &[
+    (Provider::Gemini, Scope::User, Window::Hour, 20),
+    (Provider::Gemini, Scope::User, Window::Day, 100),
+    (Provider::Gemini, Scope::Guild, Window::Day, 300),
+    (Provider::Gemini, Scope::Global, Window::Day, 1000),
+    (Provider::Imagen, Scope::User, Window::Hour, 5),
+    (Provider::Imagen, Scope::User, Window::Day, 20),
+    (Provider::Imagen, Scope::Guild, Window::Day, 60),
+    (Provider::Imagen, Scope::Global, Window::Day, 200),
+];
+
+/// The limits for `provider`, with any environment overrides applied.
+fn limits(provider: Provider) -> Vec<Limit> {
+    DEFAULT_LIMITS
+        .iter()
+        .filter(|(p, ..)| *p == provider)
+        .filter_map(|&(_, scope, window, default)| {
+            let var = format!(
+                "QUOTA_{}_{}_{}",
+                provider.as_str().to_uppercase(),
+                scope.as_str(),
+                window.as_str()
+            );
+            let max = match env::var(&var).ok().map(|v| v.trim().parse::<u32>()) {
+                Some(Ok(max)) => max,
+                Some(Err(e)) => {
+                    log::warn!("Ignoring invalid {}: {}", var, e);
+                    default
+                }
+                None => default,
+            };
+            (max > 0).then_some(Limit { scope, window, max })
+        })
+        .collect()
+}
+
+struct LimitStatus {
+    limit: Limit,
+    used: u32,
+    /// Unix timestamp of when the oldest counted request stops counting.
+    resets_at: Option<u64>,
+}
+
+impl LimitStatus {
+    fn exceeded(&self) -> bool {
+        self.used >= self.limit.max
+    }
+
+    fn field(&self) -> EmbedFieldBuilder {
+        let window = match self.limit.window {
+            Window::Hour => "hourly",
+            Window::Day => "daily",
+        };
+        let name = match self.limit.scope {
+            Scope::User => format!("Your {} limit", window),
+            Scope::Guild => format!("This server's {} limit", window),
+            Scope::Global => format!("The bot's {} limit", window),
+        };
+
+        let mut value = format!(
+            "{} of {} left",
+            self.limit.max.saturating_sub(self.used),
+            self.limit.max
+        );
+        if let Some(resets_at) = self.resets_at {
+            value += &format!(" · next one frees up <t:{}:R>", resets_at);
+        }
+        EmbedFieldBuilder::new(name, value).inline()
+    }
+}
+
+/// A request was refused because it would go over a limit.
+pub struct QuotaExceeded {
+    statuses: Vec<LimitStatus>,
+}
+
+impl QuotaExceeded {
+    pub fn embed(&self) -> Embed {
+        let retry_at = self
+            .statuses
+            .iter()
+            .filter(|s| s.exceeded())
+            .filter_map(|s| s.resets_at)
+            .max();
+        let description = match retry_at {
+            Some(retry_at) => format!(
+                "This command has been used too much for now. You can use it again <t:{}:R>.",
+                retry_at
+            ),
+            None => "This command has been used too much for now.".to_string(),
+        };
+
+        let mut quota_embed = embed::info()
+            .title("Usage limit reached")
+            .description(description);
+        for status in &self.statuses {
+            quota_embed = quota_embed.field(status.field());
+        }
+        quota_embed.build()
+    }
+}
+
+/// A request that was counted against the quotas.
+pub struct QuotaTicket {
+    usage_id: Option<i64>,
+}
+
+impl QuotaTicket {
+    /// Stops counting a request that failed.
+    pub fn refund(self, storage: &Storage) {
+        if let Some(id) = self.usage_id {
+            if let Err(e) = storage.remove_quota_usage(id) {
+                log::warn!("Failed to refund quota usage {}: {}", id, e);
+            }
+        }
+    }
+}
+
+// Held while checking and recording, so concurrent requests can't both
+// take the last slot
+static QUOTA_LOCK: Mutex<()> = Mutex::new(());
+
+/// Counts a request to `provider` against every applicable limit, or
+/// refuses it if any limit has been reached. Storage errors let the request
+/// through rather than block the command.
+pub fn acquire(
+    storage: &Storage,
+    provider: Provider,
+    user_id: Option<u64>,
+    guild_id: Option<u64>,
+) -> Result<QuotaTicket, QuotaExceeded> {
+    let _guard = QUOTA_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let now = unix_now();
+
+    let mut statuses = Vec::new();
+    for limit in limits(provider) {
+        let scope = match limit.scope {
+            Scope::User => user_id.map(UsageScope::User),
+            Scope::Guild => guild_id.map(UsageScope::Guild),
+            Scope::Global => Some(UsageScope::Global),
+        };
+        let Some(scope) = scope else {
+            continue;
+        };
+
+        let since = now.saturating_sub(limit.window.secs());
+        match storage.quota_usage(provider.as_str(), scope, since) {
+            Ok((used, oldest)) => statuses.push(LimitStatus {
+                limit,
+                used,
+                resets_at: oldest.map(|oldest| oldest + limit.window.secs()),
+            }),
+            Err(e) => log::warn!("Failed to check {:?} quota: {}", provider, e),
+        }
+    }
+
+    if statuses.iter().any(LimitStatus::exceeded) {
+        log::info!("Refused a {:?} request over quota", provider);
+        return Err(QuotaExceeded { statuses });
+    }
+
+    let usage_id = storage
+        .record_quota_usage(
+            provider.as_str(),
+            user_id,
+            guild_id,
+            now.saturating_sub(DAY_SECS),
+        )
+        .map_err(|e| log::warn!("Failed to record {:?} quota usage: {}", provider, e))
+        .ok();
+    Ok(QuotaTicket { usage_id })
+}
@@ -0,0 +1,137 @@
+use std::env;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::Connection;
+
+pub use self::jobs::{Job, JobKind, JobOrigin};
+pub use self::ledger::{LedgerEntry, LedgerGrouping, LedgerTotal};
+pub use self::nano::NanoTurn;
+pub use self::quota::UsageScope;
+pub use self::wordle::{WordleAlias, WordleConfig, WordleDay};
+
+mod jobs;
+mod ledger;
+mod migrations;
+mod nano;
+mod quota;
+mod safety;
+mod wordle;
+
+const DEFAULT_DATABASE_PATH: &str = "diffusion-bot.db";
+
+#[derive(Debug)]
+pub struct StorageError {
+    pub message: String,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError {
+            message: format!("Database error: {}", e),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Storage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl Storage {
+    /// Opens the database named by `DATABASE_URL` and brings its schema up to
+    /// date.
+    pub fn open() -> Result<Self, StorageError> {
+        let path = database_path()?;
+        let mut connection = Connection::open(&path)?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        migrations::run(&mut connection)?;
+
+        log::info!("Opened database at {}", path);
+        Ok(Storage {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The database file, from `DATABASE_URL`. `DATABASE_PATH` is still read for
+/// older deployments.
+fn database_path() -> Result<String, StorageError> {
+    match env::var("DATABASE_URL") {
+        Ok(url) => parse_database_url(&url),
+        Err(_) => {
+            Ok(env::var("DATABASE_PATH").unwrap_or_else(|_| DEFAULT_DATABASE_PATH.to_string()))
+        }
+    }
+}
+
+/// The database file in `url`, which is `sqlite://path/to/file.db`,
+/// `sqlite::memory:` or a plain path.
+fn parse_database_url(url: &str) -> Result<String, StorageError> {
+    if url == "sqlite::memory:" {
+        return Ok(":memory:".to_string());
+    }
+    if let Some(path) = url
+        .strip_prefix("sqlite://")
+        .or_else(|| url.strip_prefix("sqlite:"))
+    {
+        return Ok(path.to_string());
+    }
+    match url.split_once("://") {
+        Some((scheme, _)) => Err(StorageError {
+            message: format!("Unsupported database in DATABASE_URL: {}", scheme),
+        }),
+        None => Ok(url.to_string()),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sqlite_urls() {
+        assert_eq!(
+            parse_database_url("sqlite://data/bot.db").unwrap(),
+            "data/bot.db"
+        );
+        assert_eq!(parse_database_url("sqlite:bot.db").unwrap(), "bot.db");
+        assert_eq!(parse_database_url("sqlite::memory:").unwrap(), ":memory:");
+    }
+
+    #[test]
+    fn treats_a_url_without_a_scheme_as_a_path() {
+        assert_eq!(
+            parse_database_url("/var/lib/bot/bot.db").unwrap(),
+            "/var/lib/bot/bot.db"
+        );
+    }
+
+    #[test]
+    fn rejects_other_databases() {
+        let e = parse_database_url("postgres://localhost/bot").unwrap_err();
+        assert_eq!(e.message, "Unsupported database in DATABASE_URL: postgres");
+    }
+}
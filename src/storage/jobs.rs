@@ -0,0 +1,133 @@
+//! Generations handed to a provider, kept so polling can resume after a
+//! restart.
+
+use rusqlite::params;
+
+use super::{Storage, StorageError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobKind {
+    Horde,
+    Chat,
+    Describe,
+    HordeText,
+}
+
+impl JobKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Horde => "horde",
+            JobKind::Chat => "chat",
+            JobKind::Describe => "describe",
+            JobKind::HordeText => "horde_text",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "horde" => Some(JobKind::Horde),
+            "chat" => Some(JobKind::Chat),
+            "describe" => Some(JobKind::Describe),
+            "horde_text" => Some(JobKind::HordeText),
+            _ => None,
+        }
+    }
+}
+
+/// Who started a job and when, so a resumed job can still be accounted for.
+#[derive(Debug, Clone, Copy)]
+pub struct JobOrigin {
+    pub user_id: Option<u64>,
+    pub guild_id: Option<u64>,
+    /// Unix timestamp (seconds) of when the interaction was created, which
+    /// can be well before the provider accepted the job.
+    pub created_at: u64,
+}
+
+/// A generation that was handed to a provider but whose interaction has not
+/// been updated with the final result yet.
+#[derive(Debug)]
+pub struct Job {
+    pub id: i64,
+    pub kind: JobKind,
+    pub interaction_token: String,
+    pub provider_job_id: String,
+    /// Command parameters, serialized as JSON by the owning command.
+    pub params: String,
+    pub origin: JobOrigin,
+}
+
+impl Storage {
+    pub fn insert_job(
+        &self,
+        kind: JobKind,
+        interaction_token: &str,
+        origin: JobOrigin,
+        provider_job_id: &str,
+        params: &str,
+    ) -> Result<i64, StorageError> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT INTO jobs (kind, interaction_token, provider_job_id, params, created_at,
+                user_id, guild_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                kind.as_str(),
+                interaction_token,
+                provider_job_id,
+                params,
+                origin.created_at as i64,
+                origin.user_id.map(|id| id as i64),
+                origin.guild_id.map(|id| id as i64)
+            ],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    pub fn remove_job(&self, id: i64) -> Result<(), StorageError> {
+        self.connection()
+            .execute("DELETE FROM jobs WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn pending_jobs(&self) -> Result<Vec<Job>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, kind, interaction_token, provider_job_id, params, created_at, user_id,
+                guild_id
+             FROM jobs ORDER BY id",
+        )?;
+
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                JobOrigin {
+                    created_at: row.get::<_, i64>(5)? as u64,
+                    user_id: row.get::<_, Option<i64>>(6)?.map(|id| id as u64),
+                    guild_id: row.get::<_, Option<i64>>(7)?.map(|id| id as u64),
+                },
+            ))
+        })?;
+
+        let mut jobs = Vec::new();
+        for row in rows {
+            let (id, kind, interaction_token, provider_job_id, params, origin) = row?;
+            match JobKind::parse(&kind) {
+                Some(kind) => jobs.push(Job {
+                    id,
+                    kind,
+                    interaction_token,
+                    provider_job_id,
+                    params,
+                    origin,
+                }),
+                None => log::warn!("Skipping job {} with unknown kind '{}'", id, kind),
+            }
+        }
+        Ok(jobs)
+    }
+}
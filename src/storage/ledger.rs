@@ -0,0 +1,113 @@
+//! The ledger of provider calls and their estimated cost.
+
+use rusqlite::params;
+
+use super::{unix_now, Storage, StorageError};
+
+/// One provider call, as recorded in the ledger.
+#[derive(Debug)]
+pub struct LedgerEntry {
+    pub command: String,
+    pub user_id: Option<u64>,
+    pub guild_id: Option<u64>,
+    pub provider: String,
+    pub model: String,
+    pub tier: Option<String>,
+    pub images: u32,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub latency_ms: u64,
+    pub success: bool,
+    /// Estimated cost in US dollars.
+    pub cost: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum LedgerGrouping {
+    User,
+    Model,
+    /// Every call in one row.
+    Total,
+}
+
+/// Ledger totals for one user or model.
+#[derive(Debug)]
+pub struct LedgerTotal {
+    /// The user id or model name, or "unknown" for calls without a user.
+    pub key: String,
+    pub calls: u64,
+    pub failures: u64,
+    pub images: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: f64,
+}
+
+impl Storage {
+    pub fn insert_ledger_entry(&self, entry: &LedgerEntry) -> Result<(), StorageError> {
+        self.connection().execute(
+            "INSERT INTO ledger (created_at, command, user_id, guild_id, provider, model, tier,
+                images, input_tokens, output_tokens, latency_ms, success, cost)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                unix_now() as i64,
+                entry.command,
+                entry.user_id.map(|id| id as i64),
+                entry.guild_id.map(|id| id as i64),
+                entry.provider,
+                entry.model,
+                entry.tier,
+                entry.images,
+                entry.input_tokens as i64,
+                entry.output_tokens as i64,
+                entry.latency_ms as i64,
+                entry.success,
+                entry.cost
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Totals of the calls made since `since`, in `guild_id` or everywhere,
+    /// most expensive first.
+    pub fn ledger_totals(
+        &self,
+        grouping: LedgerGrouping,
+        since: u64,
+        guild_id: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<LedgerTotal>, StorageError> {
+        let key = match grouping {
+            LedgerGrouping::User => "COALESCE(CAST(user_id AS TEXT), 'unknown')",
+            LedgerGrouping::Model => "model",
+            LedgerGrouping::Total => "'total'",
+        };
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {key}, COUNT(*), SUM(NOT success), SUM(images), SUM(input_tokens),
+                SUM(output_tokens), SUM(cost)
+             FROM ledger
+             WHERE created_at >= ?1 AND (?2 IS NULL OR guild_id = ?2)
+             GROUP BY {key}
+             ORDER BY SUM(cost) DESC, COUNT(*) DESC, {key}
+             LIMIT ?3"
+        ))?;
+        let totals = statement
+            .query_map(
+                params![since as i64, guild_id.map(|id| id as i64), limit as i64],
+                |row| {
+                    Ok(LedgerTotal {
+                        key: row.get(0)?,
+                        calls: row.get::<_, i64>(1)? as u64,
+                        failures: row.get::<_, i64>(2)? as u64,
+                        images: row.get::<_, i64>(3)? as u64,
+                        input_tokens: row.get::<_, i64>(4)? as u64,
+                        output_tokens: row.get::<_, i64>(5)? as u64,
+                        cost: row.get(6)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(totals)
+    }
+}
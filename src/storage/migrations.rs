@@ -0,0 +1,275 @@
+//! Schema migrations, applied in order on startup. A database's
+//! `user_version` is the number of migrations it has had applied.
+//!
+//! Databases from before migrations were versioned report version 0 but may
+//! already have any of the tables up to the ledger, so those migrations only
+//! create what is missing.
+
+use rusqlite::{Connection, Transaction};
+
+use super::StorageError;
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+const MIGRATIONS: &[(&str, Migration)] = &[
+    (
+        "create jobs, nano sessions and safety settings",
+        initial_schema,
+    ),
+    ("add nano session config", nano_session_config),
+    ("create quota usage", quota_usage),
+    ("create ledger", ledger),
+    ("create wordle settings", wordle_settings),
+    ("create wordle results", wordle_results),
+    ("add job owners", job_owners),
+];
+
+/// Applies every migration the database has not had yet, each in its own
+/// transaction.
+pub(super) fn run(connection: &mut Connection) -> Result<(), StorageError> {
+    let version: usize =
+        connection.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? as usize;
+    if version > MIGRATIONS.len() {
+        return Err(StorageError {
+            message: format!(
+                "Database schema version {} is newer than this build supports ({})",
+                version,
+                MIGRATIONS.len()
+            ),
+        });
+    }
+
+    for (index, (name, migration)) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        migration(&transaction)?;
+        // PRAGMA arguments can't be bound as parameters
+        transaction.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+        transaction.commit()?;
+        log::info!("Applied database migration {}: {}", index + 1, name);
+    }
+    Ok(())
+}
+
+fn initial_schema(transaction: &Transaction) -> rusqlite::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS jobs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            interaction_token TEXT NOT NULL,
+            provider_job_id TEXT NOT NULL,
+            params TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS nano_sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            model TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS nano_turns (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id INTEGER NOT NULL REFERENCES nano_sessions (id) ON DELETE CASCADE,
+            role TEXT NOT NULL,
+            text TEXT
+        );
+        CREATE TABLE IF NOT EXISTS nano_turn_images (
+            turn_id INTEGER NOT NULL REFERENCES nano_turns (id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            data BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS guild_safety_settings (
+            guild_id INTEGER NOT NULL,
+            category TEXT NOT NULL,
+            threshold TEXT NOT NULL,
+            PRIMARY KEY (guild_id, category)
+        );",
+    )
+}
+
+fn nano_session_config(transaction: &Transaction) -> rusqlite::Result<()> {
+    let has_config: bool = transaction.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('nano_sessions') WHERE name = 'config'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_config {
+        return Ok(());
+    }
+    transaction
+        .execute_batch("ALTER TABLE nano_sessions ADD COLUMN config TEXT NOT NULL DEFAULT '{}'")
+}
+
+fn quota_usage(transaction: &Transaction) -> rusqlite::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS quota_usage (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            provider TEXT NOT NULL,
+            user_id INTEGER,
+            guild_id INTEGER,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS quota_usage_provider_created_at
+            ON quota_usage (provider, created_at);",
+    )
+}
+
+fn ledger(transaction: &Transaction) -> rusqlite::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ledger (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            created_at INTEGER NOT NULL,
+            command TEXT NOT NULL,
+            user_id INTEGER,
+            guild_id INTEGER,
+            provider TEXT NOT NULL,
+            model TEXT NOT NULL,
+            tier TEXT,
+            images INTEGER NOT NULL,
+            input_tokens INTEGER NOT NULL,
+            output_tokens INTEGER NOT NULL,
+            latency_ms INTEGER NOT NULL,
+            success INTEGER NOT NULL,
+            cost REAL NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS ledger_created_at ON ledger (created_at);",
+    )
+}
+
+fn wordle_settings(transaction: &Transaction) -> rusqlite::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE wordle_guilds (
+            guild_id INTEGER PRIMARY KEY,
+            channel_id INTEGER NOT NULL,
+            bot_id INTEGER NOT NULL
+        );
+        CREATE TABLE wordle_aliases (
+            guild_id INTEGER NOT NULL REFERENCES wordle_guilds (guild_id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            PRIMARY KEY (guild_id, name)
+        );
+        CREATE TABLE wordle_overrides (
+            guild_id INTEGER NOT NULL REFERENCES wordle_guilds (guild_id) ON DELETE CASCADE,
+            user_id INTEGER NOT NULL,
+            score INTEGER NOT NULL,
+            PRIMARY KEY (guild_id, user_id)
+        );",
+    )
+}
+
+fn wordle_results(transaction: &Transaction) -> rusqlite::Result<()> {
+    transaction.execute_batch(
+        "ALTER TABLE wordle_guilds ADD COLUMN last_message_id INTEGER;
+        CREATE TABLE wordle_days (
+            guild_id INTEGER NOT NULL REFERENCES wordle_guilds (guild_id) ON DELETE CASCADE,
+            message_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            PRIMARY KEY (guild_id, message_id)
+        );
+        CREATE TABLE wordle_scores (
+            guild_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            score INTEGER NOT NULL,
+            PRIMARY KEY (guild_id, message_id, user_id),
+            FOREIGN KEY (guild_id, message_id)
+                REFERENCES wordle_days (guild_id, message_id) ON DELETE CASCADE
+        );",
+    )
+}
+
+fn job_owners(transaction: &Transaction) -> rusqlite::Result<()> {
+    transaction.execute_batch(
+        "ALTER TABLE jobs ADD COLUMN user_id INTEGER;
+        ALTER TABLE jobs ADD COLUMN guild_id INTEGER;",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(connection: &Connection) -> usize {
+        connection
+            .query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+            .unwrap() as usize
+    }
+
+    fn has_column(connection: &Connection, table: &str, column: &str) -> bool {
+        connection
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+                [table, column],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_a_new_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        run(&mut connection).unwrap();
+        assert_eq!(user_version(&connection), MIGRATIONS.len());
+        assert!(has_column(&connection, "nano_sessions", "config"));
+        assert!(has_column(&connection, "jobs", "user_id"));
+        assert!(has_column(&connection, "wordle_guilds", "last_message_id"));
+
+        // Nothing is left to apply the second time
+        run(&mut connection).unwrap();
+        assert_eq!(user_version(&connection), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrates_a_database_from_before_versioning() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE jobs (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    kind TEXT NOT NULL,
+                    interaction_token TEXT NOT NULL,
+                    provider_job_id TEXT NOT NULL,
+                    params TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                );
+                CREATE TABLE nano_sessions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    model TEXT NOT NULL,
+                    created_at INTEGER NOT NULL,
+                    config TEXT NOT NULL DEFAULT '{}'
+                );
+                CREATE TABLE quota_usage (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    provider TEXT NOT NULL,
+                    user_id INTEGER,
+                    guild_id INTEGER,
+                    created_at INTEGER NOT NULL
+                );
+                INSERT INTO jobs (kind, interaction_token, provider_job_id, params, created_at)
+                    VALUES ('horde', 'token', 'abc', '{}', 0);
+                INSERT INTO nano_sessions (model, created_at) VALUES ('model', 0);",
+            )
+            .unwrap();
+        assert_eq!(user_version(&connection), 0);
+
+        run(&mut connection).unwrap();
+        assert_eq!(user_version(&connection), MIGRATIONS.len());
+        assert!(has_column(&connection, "jobs", "guild_id"));
+        let (jobs, sessions): (i64, i64) = connection
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM jobs), (SELECT COUNT(*) FROM nano_sessions)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((jobs, sessions), (1, 1));
+    }
+
+    #[test]
+    fn rejects_a_newer_schema() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len() + 1))
+            .unwrap();
+        assert!(run(&mut connection).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! /nano editing sessions and their turns.
+
+use rusqlite::{params, OptionalExtension};
+
+use super::{unix_now, Storage, StorageError};
+
+/// Seconds a /nano editing session can be continued for.
+const NANO_SESSION_LIFETIME: u64 = 24 * 60 * 60;
+
+/// One message of a /nano editing session, as sent to or received from Gemini.
+/// Images are stored encoded, in whichever format they were sent or received.
+#[derive(Clone, Debug)]
+pub struct NanoTurn {
+    pub role: String,
+    pub text: Option<String>,
+    pub images: Vec<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct NanoSession {
+    pub model: String,
+    /// Output options chosen when the session started, as JSON.
+    pub config: String,
+    /// The most recent turns, oldest first.
+    pub turns: Vec<NanoTurn>,
+}
+
+impl Storage {
+    /// Starts a new editing session, pruning sessions that can no longer be
+    /// continued.
+    pub fn create_nano_session(&self, model: &str, config: &str) -> Result<i64, StorageError> {
+        let connection = self.connection();
+        let now = unix_now();
+        connection.execute(
+            "DELETE FROM nano_sessions WHERE created_at < ?1",
+            params![now.saturating_sub(NANO_SESSION_LIFETIME) as i64],
+        )?;
+        connection.execute(
+            "INSERT INTO nano_sessions (model, config, created_at) VALUES (?1, ?2, ?3)",
+            params![model, config, now as i64],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    pub fn append_nano_turns(
+        &self,
+        session_id: i64,
+        turns: &[NanoTurn],
+    ) -> Result<(), StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        for turn in turns {
+            transaction.execute(
+                "INSERT INTO nano_turns (session_id, role, text) VALUES (?1, ?2, ?3)",
+                params![session_id, turn.role, turn.text],
+            )?;
+            let turn_id = transaction.last_insert_rowid();
+            for (position, image) in turn.images.iter().enumerate() {
+                transaction.execute(
+                    "INSERT INTO nano_turn_images (turn_id, position, data) VALUES (?1, ?2, ?3)",
+                    params![turn_id, position as i64, image],
+                )?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Loads a session with at most `max_turns` of its latest turns, or
+    /// `None` if it does not exist or has expired.
+    pub fn nano_session(
+        &self,
+        session_id: i64,
+        max_turns: usize,
+    ) -> Result<Option<NanoSession>, StorageError> {
+        let connection = self.connection();
+
+        let session: Option<(String, String)> = connection
+            .query_row(
+                "SELECT model, config FROM nano_sessions WHERE id = ?1 AND created_at >= ?2",
+                params![
+                    session_id,
+                    unix_now().saturating_sub(NANO_SESSION_LIFETIME) as i64
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((model, config)) = session else {
+            return Ok(None);
+        };
+
+        let mut turn_statement = connection.prepare(
+            "SELECT id, role, text FROM nano_turns
+             WHERE session_id = ?1 ORDER BY id DESC LIMIT ?2",
+        )?;
+        let mut image_statement = connection
+            .prepare("SELECT data FROM nano_turn_images WHERE turn_id = ?1 ORDER BY position")?;
+
+        let rows = turn_statement.query_map(params![session_id, max_turns as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        let mut turns = Vec::new();
+        for row in rows {
+            let (turn_id, role, text) = row?;
+            let images = image_statement
+                .query_map(params![turn_id], |row| row.get::<_, Vec<u8>>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            turns.push(NanoTurn { role, text, images });
+        }
+        turns.reverse();
+
+        Ok(Some(NanoSession {
+            model,
+            config,
+            turns,
+        }))
+    }
+}
@@ -0,0 +1,74 @@
+//! Requests counted against the provider quotas.
+
+use rusqlite::params;
+
+use super::{unix_now, Storage, StorageError};
+
+/// Whose requests to count towards a quota.
+#[derive(Clone, Copy, Debug)]
+pub enum UsageScope {
+    User(u64),
+    Guild(u64),
+    Global,
+}
+
+impl Storage {
+    /// Counts a request against the quotas of `provider`, dropping records
+    /// older than `prune_before` since no quota window reaches back that far.
+    pub fn record_quota_usage(
+        &self,
+        provider: &str,
+        user_id: Option<u64>,
+        guild_id: Option<u64>,
+        prune_before: u64,
+    ) -> Result<i64, StorageError> {
+        let connection = self.connection();
+        connection.execute(
+            "DELETE FROM quota_usage WHERE created_at < ?1",
+            params![prune_before as i64],
+        )?;
+        connection.execute(
+            "INSERT INTO quota_usage (provider, user_id, guild_id, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                provider,
+                user_id.map(|id| id as i64),
+                guild_id.map(|id| id as i64),
+                unix_now() as i64
+            ],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    /// Stops counting a request, e.g. one that failed.
+    pub fn remove_quota_usage(&self, id: i64) -> Result<(), StorageError> {
+        self.connection()
+            .execute("DELETE FROM quota_usage WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// The number of requests to `provider` in `scope` since `since`, and
+    /// when the oldest of them was made.
+    pub fn quota_usage(
+        &self,
+        provider: &str,
+        scope: UsageScope,
+        since: u64,
+    ) -> Result<(u32, Option<u64>), StorageError> {
+        let (filter, id) = match scope {
+            UsageScope::User(id) => ("AND user_id = ?3", Some(id as i64)),
+            UsageScope::Guild(id) => ("AND guild_id = ?3", Some(id as i64)),
+            UsageScope::Global => ("AND ?3 IS NULL", None),
+        };
+        let (count, oldest): (u32, Option<i64>) = self.connection().query_row(
+            &format!(
+                "SELECT COUNT(*), MIN(created_at) FROM quota_usage
+                 WHERE provider = ?1 AND created_at >= ?2 {}",
+                filter
+            ),
+            params![provider, since as i64, id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((count, oldest.map(|oldest| oldest as u64)))
+    }
+}
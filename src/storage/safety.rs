@@ -0,0 +1,46 @@
+//! Per-guild Gemini safety filter overrides.
+
+use rusqlite::params;
+
+use super::{Storage, StorageError};
+
+impl Storage {
+    /// The Gemini safety thresholds a guild has overridden, by category.
+    pub fn safety_settings(&self, guild_id: u64) -> Result<Vec<(String, String)>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT category, threshold FROM guild_safety_settings
+             WHERE guild_id = ?1 ORDER BY category",
+        )?;
+        let settings = statement
+            .query_map(params![guild_id as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(settings)
+    }
+
+    /// Overrides the threshold for `category`, or goes back to the model's
+    /// default when `threshold` is `None`.
+    pub fn set_safety_setting(
+        &self,
+        guild_id: u64,
+        category: &str,
+        threshold: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let connection = self.connection();
+        match threshold {
+            Some(threshold) => connection.execute(
+                "INSERT INTO guild_safety_settings (guild_id, category, threshold)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (guild_id, category) DO UPDATE SET threshold = excluded.threshold",
+                params![guild_id as i64, category, threshold],
+            )?,
+            None => connection.execute(
+                "DELETE FROM guild_safety_settings WHERE guild_id = ?1 AND category = ?2",
+                params![guild_id as i64, category],
+            )?,
+        };
+        Ok(())
+    }
+}
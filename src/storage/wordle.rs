@@ -0,0 +1,261 @@
+//! Per-guild Wordle tracking: where the results are posted, how to recognise
+//! players the results bot does not mention, and the results read so far.
+
+use std::collections::HashMap;
+
+use rusqlite::{params, OptionalExtension};
+
+use super::{Storage, StorageError};
+
+/// A name the results bot writes in place of a mention, e.g. "@rmanky".
+#[derive(Clone, Debug)]
+pub struct WordleAlias {
+    pub name: String,
+    pub user_id: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct WordleConfig {
+    /// The channel the daily results are posted in.
+    pub channel_id: u64,
+    /// The bot that posts them.
+    pub bot_id: u64,
+    pub aliases: Vec<WordleAlias>,
+    /// Scores for the first tracked day, by user id, for players the results
+    /// bot did not record that day.
+    pub day_one_overrides: Vec<(u64, u32)>,
+    /// The newest message read from the channel, if it has been read before.
+    pub last_message_id: Option<u64>,
+}
+
+/// One results message and the scores read from it.
+#[derive(Clone, Debug)]
+pub struct WordleDay {
+    pub message_id: u64,
+    pub content: String,
+    /// Scores by user id.
+    pub scores: HashMap<u64, u32>,
+}
+
+impl Storage {
+    /// The tracked channel and results bot, or `None` if the guild has not
+    /// been set up. Cheaper than `wordle_config` for checking every message.
+    pub fn wordle_channel(&self, guild_id: u64) -> Result<Option<(u64, u64)>, StorageError> {
+        let channel: Option<(i64, i64)> = self
+            .connection()
+            .query_row(
+                "SELECT channel_id, bot_id FROM wordle_guilds WHERE guild_id = ?1",
+                params![guild_id as i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(channel.map(|(channel_id, bot_id)| (channel_id as u64, bot_id as u64)))
+    }
+
+    /// The guild's tracking settings, or `None` if it has not been set up.
+    pub fn wordle_config(&self, guild_id: u64) -> Result<Option<WordleConfig>, StorageError> {
+        let connection = self.connection();
+
+        let channel: Option<(i64, i64, Option<i64>)> = connection
+            .query_row(
+                "SELECT channel_id, bot_id, last_message_id FROM wordle_guilds WHERE guild_id = ?1",
+                params![guild_id as i64],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let Some((channel_id, bot_id, last_message_id)) = channel else {
+            return Ok(None);
+        };
+
+        let aliases = connection
+            .prepare("SELECT name, user_id FROM wordle_aliases WHERE guild_id = ?1 ORDER BY name")?
+            .query_map(params![guild_id as i64], |row| {
+                Ok(WordleAlias {
+                    name: row.get(0)?,
+                    user_id: row.get::<_, i64>(1)? as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let day_one_overrides = connection
+            .prepare(
+                "SELECT user_id, score FROM wordle_overrides WHERE guild_id = ?1 ORDER BY user_id",
+            )?
+            .query_map(params![guild_id as i64], |row| {
+                Ok((row.get::<_, i64>(0)? as u64, row.get(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(WordleConfig {
+            channel_id: channel_id as u64,
+            bot_id: bot_id as u64,
+            aliases,
+            day_one_overrides,
+            last_message_id: last_message_id.map(|id| id as u64),
+        }))
+    }
+
+    /// Sets where the guild's results are posted. Results read from a
+    /// previous channel or bot are forgotten.
+    pub fn set_wordle_channel(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        bot_id: u64,
+    ) -> Result<(), StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let unchanged: bool = transaction
+            .query_row(
+                "SELECT channel_id = ?2 AND bot_id = ?3 FROM wordle_guilds WHERE guild_id = ?1",
+                params![guild_id as i64, channel_id as i64, bot_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(false);
+        if !unchanged {
+            transaction.execute(
+                "DELETE FROM wordle_days WHERE guild_id = ?1",
+                params![guild_id as i64],
+            )?;
+        }
+        transaction.execute(
+            "INSERT INTO wordle_guilds (guild_id, channel_id, bot_id) VALUES (?1, ?2, ?3)
+             ON CONFLICT (guild_id) DO UPDATE
+                SET channel_id = excluded.channel_id,
+                    bot_id = excluded.bot_id,
+                    last_message_id = CASE WHEN ?4 THEN last_message_id END",
+            params![guild_id as i64, channel_id as i64, bot_id as i64, unchanged],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Stores a results message, replacing the scores read from it before.
+    pub fn store_wordle_day(&self, guild_id: u64, day: &WordleDay) -> Result<(), StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO wordle_days (guild_id, message_id, content) VALUES (?1, ?2, ?3)
+             ON CONFLICT (guild_id, message_id) DO UPDATE SET content = excluded.content",
+            params![guild_id as i64, day.message_id as i64, day.content],
+        )?;
+        transaction.execute(
+            "DELETE FROM wordle_scores WHERE guild_id = ?1 AND message_id = ?2",
+            params![guild_id as i64, day.message_id as i64],
+        )?;
+        for (user_id, score) in &day.scores {
+            transaction.execute(
+                "INSERT INTO wordle_scores (guild_id, message_id, user_id, score)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    guild_id as i64,
+                    day.message_id as i64,
+                    *user_id as i64,
+                    score
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Records that the channel has been read up to `message_id`. The
+    /// position never moves backwards.
+    pub fn advance_wordle_cursor(
+        &self,
+        guild_id: u64,
+        message_id: u64,
+    ) -> Result<(), StorageError> {
+        self.connection().execute(
+            "UPDATE wordle_guilds SET last_message_id = MAX(COALESCE(last_message_id, 0), ?2)
+             WHERE guild_id = ?1",
+            params![guild_id as i64, message_id as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Every stored results message of the guild, oldest first.
+    pub fn wordle_days(&self, guild_id: u64) -> Result<Vec<WordleDay>, StorageError> {
+        let connection = self.connection();
+        let mut days: Vec<WordleDay> = connection
+            .prepare(
+                "SELECT message_id, content FROM wordle_days
+                 WHERE guild_id = ?1 ORDER BY message_id",
+            )?
+            .query_map(params![guild_id as i64], |row| {
+                Ok(WordleDay {
+                    message_id: row.get::<_, i64>(0)? as u64,
+                    content: row.get(1)?,
+                    scores: HashMap::new(),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let index: HashMap<u64, usize> = days
+            .iter()
+            .enumerate()
+            .map(|(i, day)| (day.message_id, i))
+            .collect();
+        let mut statement = connection
+            .prepare("SELECT message_id, user_id, score FROM wordle_scores WHERE guild_id = ?1")?;
+        let scores = statement.query_map(params![guild_id as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)? as u64,
+                row.get::<_, i64>(1)? as u64,
+                row.get::<_, u32>(2)?,
+            ))
+        })?;
+        for score in scores {
+            let (message_id, user_id, score) = score?;
+            if let Some(&i) = index.get(&message_id) {
+                days[i].scores.insert(user_id, score);
+            }
+        }
+        Ok(days)
+    }
+
+    /// Maps `name` to a user, or removes the alias when `user_id` is `None`.
+    pub fn set_wordle_alias(
+        &self,
+        guild_id: u64,
+        name: &str,
+        user_id: Option<u64>,
+    ) -> Result<(), StorageError> {
+        let connection = self.connection();
+        match user_id {
+            Some(user_id) => connection.execute(
+                "INSERT INTO wordle_aliases (guild_id, name, user_id) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (guild_id, name) DO UPDATE SET user_id = excluded.user_id",
+                params![guild_id as i64, name, user_id as i64],
+            )?,
+            None => connection.execute(
+                "DELETE FROM wordle_aliases WHERE guild_id = ?1 AND name = ?2",
+                params![guild_id as i64, name],
+            )?,
+        };
+        Ok(())
+    }
+
+    /// Sets a player's first day score, or removes it when `score` is `None`.
+    pub fn set_wordle_override(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        score: Option<u32>,
+    ) -> Result<(), StorageError> {
+        let connection = self.connection();
+        match score {
+            Some(score) => connection.execute(
+                "INSERT INTO wordle_overrides (guild_id, user_id, score) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (guild_id, user_id) DO UPDATE SET score = excluded.score",
+                params![guild_id as i64, user_id as i64, score],
+            )?,
+            None => connection.execute(
+                "DELETE FROM wordle_overrides WHERE guild_id = ?1 AND user_id = ?2",
+                params![guild_id as i64, user_id as i64],
+            )?,
+        };
+        Ok(())
+    }
+}
@@ -1,18 +1,201 @@
-use std::env;
+use std::fmt;
+use std::time::Duration;
 
+use futures::StreamExt;
 use log::info;
-use reqwest::Client;
-use serde_json::Value;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use reqwest_eventsource::retry::Never;
+use reqwest_eventsource::{Error as EventSourceError, Event, EventSource};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+mod keys;
+mod models;
+
+use super::retry::{self, Idempotency, Retry, RetryObserver};
+
+use keys::{available_keys, record_failure, record_success, until_daily_reset, PooledKey};
+pub use keys::{format_duration, key_health};
+pub use models::*;
+
+pub const GOOGLE_AI_API_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+const LIST_MODELS_PAGE_SIZE: &str = "1000";
+
+/// What went wrong, so callers can decide how to report or handle an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Rate limited or out of quota.
+    Quota,
+    /// The prompt or the output was blocked by a safety filter.
+    Safety,
+    /// The request itself was rejected, e.g. an unknown model or a bad image.
+    InvalidArgument,
+    /// The key is missing, invalid or cannot be used for this request.
+    Auth,
+    /// Google had an internal error or was unavailable.
+    Server,
+    /// The request never got a response.
+    Network,
+    /// The response did not have the expected shape.
+    Parse,
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether another key could succeed where this one failed.
+    fn try_next_key(self) -> bool {
+        !matches!(
+            self,
+            ErrorKind::Safety | ErrorKind::InvalidArgument | ErrorKind::Parse
+        )
+    }
+}
 
 #[derive(Debug)]
 pub struct GoogleAiError {
+    pub kind: ErrorKind,
     pub message: String,
+    /// When the request can be retried, if Google said so.
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for GoogleAiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl GoogleAiError {
+    fn new(kind: ErrorKind, message: String) -> Self {
+        GoogleAiError {
+            kind,
+            message,
+            retry_after: None,
+        }
+    }
+
+    /// Classifies a non-2xx response from its status and Google's error body.
+    fn from_response(tier: &str, status_code: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        let header_retry_after = retry::retry_after(headers);
+
+        let Ok(ErrorResponse { error }) = serde_json::from_str::<ErrorResponse>(body) else {
+            return GoogleAiError {
+                kind: classify(status_code, "", false),
+                message: format!("API Error with tier {} ({}):\n{}", tier, status_code, body),
+                retry_after: header_retry_after,
+            };
+        };
+
+        let key_invalid = error.details.iter().any(|d| d.reason == "API_KEY_INVALID");
+        let retry_delay = error
+            .details
+            .iter()
+            .find_map(|d| d.retry_delay.as_deref())
+            .and_then(parse_retry_delay);
+        let daily_quota = error
+            .details
+            .iter()
+            .flat_map(|d| &d.violations)
+            .any(|v| v.quota_id.contains("PerDay"));
+
+        GoogleAiError {
+            kind: classify(status_code, &error.status, key_invalid),
+            message: format!(
+                "API Error with tier {} ({} {}):\n{}",
+                tier, status_code, error.status, error.message
+            ),
+            retry_after: retry_delay
+                .or(header_retry_after)
+                .or(daily_quota.then(until_daily_reset)),
+        }
+    }
+
+    /// A safety block, listing any ratings worth mentioning.
+    pub fn blocked(reason: String, message: Option<String>, ratings: &[SafetyRating]) -> Self {
+        let mut lines = vec![reason];
+        lines.extend(message);
+        lines.extend(describe_safety_ratings(ratings));
+        GoogleAiError::new(ErrorKind::Safety, lines.join("\n"))
+    }
+}
+
+fn classify(status_code: StatusCode, status: &str, key_invalid: bool) -> ErrorKind {
+    if key_invalid {
+        return ErrorKind::Auth;
+    }
+    match (status_code.as_u16(), status) {
+        (_, "RESOURCE_EXHAUSTED") | (429, _) => ErrorKind::Quota,
+        // Also returned when the free tier is not available in a region
+        (_, "PERMISSION_DENIED" | "UNAUTHENTICATED" | "FAILED_PRECONDITION") | (401 | 403, _) => {
+            ErrorKind::Auth
+        }
+        (_, "INVALID_ARGUMENT" | "NOT_FOUND") | (400 | 404, _) => ErrorKind::InvalidArgument,
+        (500..=599, _) => ErrorKind::Server,
+        _ => ErrorKind::Other,
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorStatus,
+}
+
+#[derive(Deserialize)]
+struct ErrorStatus {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    details: Vec<ErrorDetail>,
+}
+
+/// One entry of `details`, which holds `ErrorInfo`, `RetryInfo` and
+/// `QuotaFailure` messages among others.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorDetail {
+    #[serde(default)]
+    reason: String,
+    retry_delay: Option<String>,
+    #[serde(default)]
+    violations: Vec<QuotaViolation>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuotaViolation {
+    #[serde(default)]
+    quota_id: String,
+}
+
+/// Parses a protobuf duration such as `"37s"` or `"1.5s"`.
+fn parse_retry_delay(delay: &str) -> Option<Duration> {
+    let secs: f64 = delay.strip_suffix('s')?.parse().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Lists the ratings worth mentioning: anything blocked or rated above
+/// negligible.
+fn describe_safety_ratings(ratings: &[SafetyRating]) -> Vec<String> {
+    ratings
+        .iter()
+        .filter(|r| r.blocked || r.probability != "NEGLIGIBLE")
+        .map(|r| {
+            let category = r.category.trim_start_matches("HARM_CATEGORY_");
+            match r.blocked {
+                true => format!("{}: {} (blocked)", category, r.probability),
+                false => format!("{}: {}", category, r.probability),
+            }
+        })
+        .collect()
 }
 
 #[derive(Debug)]
-pub struct GoogleApiKey<'a> {
-    pub tier: &'a str,
-    pub env_var: &'a str,
+pub struct GoogleApiKey {
+    pub tier: &'static str,
+    pub env_var: &'static str,
 }
 
 pub const GOOGLE_API_FREE_KEY: GoogleApiKey = GoogleApiKey {
@@ -25,60 +208,289 @@ pub const GOOGLE_API_PAID_KEY: GoogleApiKey = GoogleApiKey {
     env_var: "GEMINI_API_KEY",
 };
 
-pub struct GoogleAiResponse<'a> {
-    pub text: String,
-    pub tier_used: &'a str,
-}
-
-pub async fn post_generative_ai<'a>(
-    reqwest_client: &Client,
-    api_url: &str,
-    request_body: &Value,
-    keys_to_try: &[GoogleApiKey<'a>],
-) -> Result<GoogleAiResponse<'a>, GoogleAiError> {
-    let mut last_error_message = "No API keys configured or all attempts failed".to_string();
-    for google_api_key in keys_to_try {
-        let tier_used = google_api_key.tier;
-        let api_key = match env::var(google_api_key.env_var) {
-            Ok(key) if !key.is_empty() => key,
-            _ => continue,
-        };
+pub struct GoogleAiResponse<T> {
+    pub body: T,
+    pub tier_used: &'static str,
+}
 
-        let response_result = reqwest_client
-            .post(api_url)
-            .header("x-goog-api-key", api_key)
-            .json(request_body)
-            .send()
-            .await;
+/// Calls the Gemini API with each key of `keys` in turn, moving on to the
+/// next one when a key is out of quota or otherwise unusable. Keys that ran
+/// out of quota are skipped until they reset, and transient errors are
+/// retried on the same key first.
+pub struct GoogleAiClient<'a> {
+    reqwest_client: &'a Client,
+    keys: &'a [GoogleApiKey],
+    observer: &'a dyn RetryObserver,
+}
+
+impl<'a> GoogleAiClient<'a> {
+    pub fn new(reqwest_client: &'a Client, keys: &'a [GoogleApiKey]) -> Self {
+        GoogleAiClient {
+            reqwest_client,
+            keys,
+            observer: &(),
+        }
+    }
+
+    /// Reports retries of transient errors to `observer`.
+    pub fn with_retry_observer(mut self, observer: &'a dyn RetryObserver) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Opens a `streamGenerateContent` response. A key is only given up on
+    /// if the stream fails to open.
+    pub async fn stream_generate_content(
+        &self,
+        model: &str,
+        request: &GenerateContentRequest,
+    ) -> Result<GenerateContentStream, GoogleAiError> {
+        let url = format!(
+            "{}/{}:streamGenerateContent?alt=sse",
+            GOOGLE_AI_API_URL,
+            model_path(model)
+        );
+
+        let mut last_error = None;
+        for key in available_keys(self.keys)? {
+            let error = match self.open_stream(&url, request, &key).await {
+                Ok(event_source) => {
+                    record_success(&key);
+                    return Ok(GenerateContentStream {
+                        event_source,
+                        tier_used: key.tier,
+                    });
+                }
+                Err(error) => error,
+            };
 
-        let response = match response_result {
-            Ok(resp) => resp,
-            Err(e) => {
-                last_error_message = format!("Request failed with tier {}: {}", tier_used, e);
-                info!("{}", last_error_message);
-                continue;
+            info!("{}", error.message);
+            record_failure(&key, &error);
+            if !error.kind.try_next_key() {
+                return Err(error);
             }
-        };
+            last_error = Some(error);
+        }
+
+        Err(last_error.unwrap_or_else(all_keys_failed))
+    }
 
-        let status_code = response.status();
-        let text = response.text().await.unwrap_or_default();
+    async fn open_stream(
+        &self,
+        url: &str,
+        request: &GenerateContentRequest,
+        key: &PooledKey,
+    ) -> Result<EventSource, GoogleAiError> {
+        // Every accepted generation is billed, so only connection failures
+        // are retried
+        let mut retry = Retry::new(Idempotency::NonIdempotent, self.observer);
+        loop {
+            let builder = self
+                .reqwest_client
+                .post(url)
+                .header("x-goog-api-key", &key.value)
+                .json(request);
+            let mut event_source = EventSource::new(builder).map_err(|e| {
+                GoogleAiError::new(
+                    ErrorKind::Other,
+                    format!("Request failed with tier {}: {}", key.label, e),
+                )
+            })?;
+            event_source.set_retry_policy(Box::new(Never));
 
-        if !status_code.is_success() {
-            last_error_message = format!(
-                "API Error with tier {} ({}):\n{}",
-                tier_used, status_code, text
-            );
-            info!("{}", last_error_message);
-            continue;
+            let error = match event_source.next().await {
+                Some(Ok(Event::Open)) => return Ok(event_source),
+                Some(Err(EventSourceError::InvalidStatusCode(status_code, response))) => {
+                    let headers = response.headers().clone();
+                    let text = response.text().await.unwrap_or_default();
+                    if retry.after_status(status_code, &headers).await {
+                        event_source.close();
+                        continue;
+                    }
+                    GoogleAiError::from_response(&key.label, status_code, &headers, &text)
+                }
+                Some(Err(EventSourceError::Transport(e))) => {
+                    if retry.after_error(&e).await {
+                        event_source.close();
+                        continue;
+                    }
+                    GoogleAiError::new(
+                        ErrorKind::Network,
+                        format!("Request failed with tier {}: {}", key.label, e),
+                    )
+                }
+                Some(Err(e)) => GoogleAiError::new(
+                    ErrorKind::Network,
+                    format!("Request failed with tier {}: {}", key.label, e),
+                ),
+                Some(Ok(Event::Message(_))) | None => GoogleAiError::new(
+                    ErrorKind::Other,
+                    format!("Stream with tier {} did not open", key.label),
+                ),
+            };
+            event_source.close();
+            return Err(error);
         }
+    }
 
-        return Ok(GoogleAiResponse {
-            text,
-            tier_used: tier_used,
-        });
+    pub async fn predict(
+        &self,
+        model: &str,
+        request: &PredictRequest,
+    ) -> Result<GoogleAiResponse<PredictResponse>, GoogleAiError> {
+        let url = format!("{}/{}:predict", GOOGLE_AI_API_URL, model_path(model));
+        self.send(
+            || self.reqwest_client.post(&url).json(request),
+            Idempotency::NonIdempotent,
+        )
+        .await
     }
 
-    Err(GoogleAiError {
-        message: last_error_message,
-    })
+    pub async fn count_tokens(
+        &self,
+        model: &str,
+        request: &CountTokensRequest,
+    ) -> Result<GoogleAiResponse<CountTokensResponse>, GoogleAiError> {
+        let url = format!("{}/{}:countTokens", GOOGLE_AI_API_URL, model_path(model));
+        self.send(
+            || self.reqwest_client.post(&url).json(request),
+            Idempotency::Idempotent,
+        )
+        .await
+    }
+
+    /// Every model available to the first working key, across all pages.
+    pub async fn list_models(&self) -> Result<GoogleAiResponse<Vec<Model>>, GoogleAiError> {
+        let url = format!("{}/models", GOOGLE_AI_API_URL);
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = vec![("pageSize", LIST_MODELS_PAGE_SIZE)];
+            if let Some(token) = &page_token {
+                query.push(("pageToken", token));
+            }
+
+            let response: GoogleAiResponse<ListModelsResponse> = self
+                .send(
+                    || self.reqwest_client.get(&url).query(&query),
+                    Idempotency::Idempotent,
+                )
+                .await?;
+            models.extend(response.body.models);
+
+            match response.body.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => {
+                    return Ok(GoogleAiResponse {
+                        body: models,
+                        tier_used: response.tier_used,
+                    })
+                }
+            }
+        }
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+        idempotency: Idempotency,
+    ) -> Result<GoogleAiResponse<T>, GoogleAiError> {
+        let mut last_error = None;
+        for key in available_keys(self.keys)? {
+            let response = retry::send(
+                || request().header("x-goog-api-key", &key.value),
+                idempotency,
+                self.observer,
+            )
+            .await;
+            let error = match response {
+                Ok(response) => {
+                    let status_code = response.status();
+                    let headers = response.headers().clone();
+                    let text = response.text().await.unwrap_or_default();
+                    if status_code.is_success() {
+                        record_success(&key);
+                        return serde_json::from_str(&text)
+                            .map(|body| GoogleAiResponse {
+                                body,
+                                tier_used: key.tier,
+                            })
+                            .map_err(|e| {
+                                GoogleAiError::new(
+                                    ErrorKind::Parse,
+                                    format!(
+                                        "JSON Parse Error with tier {}: {}\nResponse: {}",
+                                        key.label, e, text
+                                    ),
+                                )
+                            });
+                    }
+                    GoogleAiError::from_response(&key.label, status_code, &headers, &text)
+                }
+                Err(e) => GoogleAiError::new(
+                    ErrorKind::Network,
+                    format!("Request failed with tier {}: {}", key.label, e),
+                ),
+            };
+
+            info!("{}", error.message);
+            record_failure(&key, &error);
+            if !error.kind.try_next_key() {
+                return Err(error);
+            }
+            last_error = Some(error);
+        }
+
+        Err(last_error.unwrap_or_else(all_keys_failed))
+    }
+}
+
+fn model_path(model: &str) -> String {
+    match model.starts_with("models/") {
+        true => model.to_string(),
+        false => format!("models/{}", model),
+    }
+}
+
+fn all_keys_failed() -> GoogleAiError {
+    GoogleAiError::new(ErrorKind::Other, "All API keys failed".to_string())
+}
+
+/// An open `streamGenerateContent` response. Each chunk holds only the parts
+/// produced since the previous one.
+pub struct GenerateContentStream {
+    event_source: EventSource,
+    pub tier_used: &'static str,
+}
+
+impl GenerateContentStream {
+    /// Returns the next chunk, or `None` once the response is complete.
+    pub async fn next_chunk(&mut self) -> Option<Result<GenerateContentResponse, GoogleAiError>> {
+        while let Some(event) = self.event_source.next().await {
+            match event {
+                Ok(Event::Message(message)) => {
+                    return Some(serde_json::from_str(&message.data).map_err(|e| {
+                        GoogleAiError::new(
+                            ErrorKind::Parse,
+                            format!(
+                                "JSON Parse Error with tier {}: {}\nResponse: {}",
+                                self.tier_used, e, message.data
+                            ),
+                        )
+                    }))
+                }
+                Ok(Event::Open) => {}
+                Err(EventSourceError::StreamEnded) => break,
+                Err(e) => {
+                    self.event_source.close();
+                    return Some(Err(GoogleAiError::new(
+                        ErrorKind::Network,
+                        format!("Stream error with tier {}: {}", self.tier_used, e),
+                    )));
+                }
+            }
+        }
+        self.event_source.close();
+        None
+    }
 }
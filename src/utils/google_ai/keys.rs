@@ -0,0 +1,189 @@
+//! The pool of configured API keys and what each one last told us about its
+//! quota, so a key that is out of quota is skipped until it resets.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+
+use super::{ErrorKind, GoogleAiError, GoogleApiKey, GOOGLE_API_FREE_KEY, GOOGLE_API_PAID_KEY};
+
+/// How long a key is skipped for when Google does not say when to retry.
+const DEFAULT_QUOTA_BACKOFF: Duration = Duration::from_secs(60);
+/// Daily quotas reset at midnight Pacific time. Standard time is used all
+/// year, so during daylight saving time a key is retried an hour late.
+const PACIFIC_UTC_OFFSET_SECS: u64 = 8 * 60 * 60;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// One key of a tier, e.g. the second comma-separated value of
+/// `GOOGLE_API_FREE_KEY`.
+pub(super) struct PooledKey {
+    pub tier: &'static str,
+    pub label: String,
+    pub value: String,
+}
+
+#[derive(Default)]
+struct KeyState {
+    exhausted_until: Option<Instant>,
+    last_error: Option<String>,
+    successes: u64,
+    failures: u64,
+}
+
+// By key value, so the state follows a key if the configuration is reordered
+static KEY_STATES: Lazy<Mutex<HashMap<String, KeyState>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn key_states() -> std::sync::MutexGuard<'static, HashMap<String, KeyState>> {
+    KEY_STATES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Every key configured for `tier`, in order.
+fn pool(tier: &GoogleApiKey) -> Vec<PooledKey> {
+    let values = env::var(tier.env_var).unwrap_or_default();
+    let values: Vec<&str> = values
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect();
+    let count = values.len();
+
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| PooledKey {
+            tier: tier.tier,
+            label: match count {
+                1 => tier.tier.to_string(),
+                _ => format!("{} #{}", tier.tier, i + 1),
+            },
+            value: value.to_string(),
+        })
+        .collect()
+}
+
+/// The keys of `tiers` that are not known to be out of quota, in order.
+pub(super) fn available_keys(tiers: &[GoogleApiKey]) -> Result<Vec<PooledKey>, GoogleAiError> {
+    let keys: Vec<PooledKey> = tiers.iter().flat_map(pool).collect();
+    if keys.is_empty() {
+        return Err(GoogleAiError::new(
+            ErrorKind::Auth,
+            "No API keys configured".to_string(),
+        ));
+    }
+
+    let now = Instant::now();
+    let states = key_states();
+    let exhausted_until = |key: &PooledKey| states.get(&key.value).and_then(|s| s.exhausted_until);
+
+    let next_reset = keys
+        .iter()
+        .filter_map(exhausted_until)
+        .filter(|until| *until > now)
+        .min();
+    let available: Vec<PooledKey> = keys
+        .into_iter()
+        .filter(|key| exhausted_until(key).is_none_or(|until| until <= now))
+        .collect();
+
+    match (available.is_empty(), next_reset) {
+        (true, Some(next_reset)) => Err(GoogleAiError {
+            kind: ErrorKind::Quota,
+            message: format!(
+                "Every API key is out of quota. The first one resets in {}.",
+                format_duration(next_reset - now)
+            ),
+            retry_after: Some(next_reset - now),
+        }),
+        _ => Ok(available),
+    }
+}
+
+pub(super) fn record_success(key: &PooledKey) {
+    let mut states = key_states();
+    let state = states.entry(key.value.clone()).or_default();
+    state.successes += 1;
+    state.exhausted_until = None;
+}
+
+pub(super) fn record_failure(key: &PooledKey, error: &GoogleAiError) {
+    let mut states = key_states();
+    let state = states.entry(key.value.clone()).or_default();
+    state.failures += 1;
+    state.last_error = Some(error.message.clone());
+    if error.kind == ErrorKind::Quota {
+        let backoff = error.retry_after.unwrap_or(DEFAULT_QUOTA_BACKOFF);
+        log::warn!(
+            "Google API key {} is out of quota, skipping it for {}",
+            key.label,
+            format_duration(backoff)
+        );
+        state.exhausted_until = Some(Instant::now() + backoff);
+    }
+}
+
+/// Time until the next daily quota reset.
+pub(super) fn until_daily_reset() -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let pacific = now.saturating_sub(PACIFIC_UTC_OFFSET_SECS);
+    Duration::from_secs(SECS_PER_DAY - pacific % SECS_PER_DAY)
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// What is known about a configured key, without the key itself.
+pub struct KeyHealth {
+    pub label: String,
+    /// The last four characters of the key.
+    pub suffix: String,
+    /// How long the key is skipped for, if it is out of quota.
+    pub exhausted_for: Option<Duration>,
+    pub last_error: Option<String>,
+    pub successes: u64,
+    pub failures: u64,
+}
+
+/// The health of every configured key, free keys first.
+pub fn key_health() -> Vec<KeyHealth> {
+    let now = Instant::now();
+    let states = key_states();
+
+    [GOOGLE_API_FREE_KEY, GOOGLE_API_PAID_KEY]
+        .iter()
+        .flat_map(pool)
+        .map(|key| {
+            let state = states.get(&key.value);
+            let suffix_start = key.value.len().saturating_sub(4);
+            KeyHealth {
+                suffix: key
+                    .value
+                    .get(suffix_start..)
+                    .unwrap_or_default()
+                    .to_string(),
+                exhausted_for: state
+                    .and_then(|s| s.exhausted_until)
+                    .filter(|until| *until > now)
+                    .map(|until| until - now),
+                last_error: state.and_then(|s| s.last_error.clone()),
+                successes: state.map_or(0, |s| s.successes),
+                failures: state.map_or(0, |s| s.failures),
+                label: key.label,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use twilight_util::builder::embed::EmbedFieldBuilder;
+
+use super::retry::{self, Idempotency, RetryObserver};
+
+pub const HORDE_API_URL: &str = "https://stablehorde.net/api/v2";
+
+/// Seconds to keep polling a Horde request before giving up on it.
+pub const HORDE_TIMEOUT_SECS: u64 = 180;
+
+#[derive(Debug)]
+pub struct HordeError {
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Finished,
+    Processing,
+    Waiting,
+}
+
+/// Queue information shared by the image check and text status endpoints.
+#[derive(Deserialize)]
+pub struct HordePoll {
+    pub done: bool,
+    pub faulted: bool,
+    pub wait_time: f32,
+    pub queue_position: f32,
+}
+
+impl HordePoll {
+    pub fn status(&self) -> Result<Status, HordeError> {
+        if self.queue_position > 0.0 {
+            Ok(Status::Waiting)
+        } else if self.done {
+            Ok(Status::Finished)
+        } else if self.faulted {
+            Err(HordeError {
+                message: "An unrecoverable fault has occurred".to_string(),
+            })
+        } else {
+            Ok(Status::Processing)
+        }
+    }
+}
+
+const BAR_WIDTH: usize = 16;
+const MIN_EDIT_INTERVAL: Duration = Duration::from_secs(3);
+// Every this many concurrent polls, the minimum edit interval grows by one step
+const POLLS_PER_INTERVAL_STEP: usize = 5;
+
+static ACTIVE_POLLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Renders the pending status of a Horde request and decides when the
+/// embed is worth editing. Edits are skipped unless the status, queue
+/// position or rounded wait changed, and are spaced out further the more
+/// requests are being polled.
+pub struct Progress {
+    start: SystemTime,
+    workers: Option<u32>,
+    /// Status, queue position and rounded wait at the last edit.
+    last_status: Option<String>,
+    last_edit: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(start: SystemTime, workers: Option<u32>) -> Self {
+        ACTIVE_POLLS.fetch_add(1, Ordering::Relaxed);
+        Progress {
+            start,
+            workers,
+            last_status: None,
+            last_edit: None,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.start)
+            .unwrap_or_default()
+    }
+
+    fn min_edit_interval() -> Duration {
+        let steps = ACTIVE_POLLS
+            .load(Ordering::Relaxed)
+            .div_ceil(POLLS_PER_INTERVAL_STEP)
+            .max(1);
+        MIN_EDIT_INTERVAL * steps as u32
+    }
+
+    /// Returns the status field to show, or `None` if the embed should not
+    /// be edited this time.
+    pub fn update(&mut self, status: &Status, poll: &HordePoll) -> Option<EmbedFieldBuilder> {
+        // Rounded so that small fluctuations in the estimate don't count as a change
+        let remaining = (poll.wait_time.max(0.0) / 5.0).round() as u32 * 5;
+        // The bar moves with elapsed time, so it is left out of the comparison
+        let key = format!("{:?}|{}|{}", status, poll.queue_position, remaining);
+
+        if self.last_status.as_ref() == Some(&key) {
+            return None;
+        }
+        if let Some(last_edit) = self.last_edit {
+            if last_edit.elapsed() < Self::min_edit_interval() {
+                return None;
+            }
+        }
+
+        self.last_status = Some(key);
+        self.last_edit = Some(Instant::now());
+
+        let elapsed = self.elapsed().as_secs();
+        let mut footer = format!("Elapsed {}:{:02}", elapsed / 60, elapsed % 60);
+        if let Some(workers) = self.workers {
+            footer += &format!(" · {} workers", workers);
+        }
+
+        Some(EmbedFieldBuilder::new(
+            "Status",
+            format!(
+                "{}\n{}",
+                self.render_status(status, poll, remaining),
+                footer
+            ),
+        ))
+    }
+
+    fn render_status(&self, status: &Status, poll: &HordePoll, remaining: u32) -> String {
+        let elapsed = self.elapsed().as_secs_f32();
+        let wait_time = poll.wait_time.max(0.0);
+        let fraction = match status {
+            Status::Finished => 1.0,
+            _ if elapsed + wait_time > 0.0 => elapsed / (elapsed + wait_time),
+            _ => 0.0,
+        };
+        let filled = ((fraction * BAR_WIDTH as f32).round() as usize).min(BAR_WIDTH);
+        let bar = "▰".repeat(filled) + &"▱".repeat(BAR_WIDTH - filled);
+
+        let mut rendered = format!("**{:#?}**", status);
+        if poll.queue_position > 0.0 {
+            rendered += &format!(" · Queue position {}", poll.queue_position);
+        }
+        rendered += &format!("\n`{}` ~{}s remaining", bar, remaining);
+        rendered
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        ACTIVE_POLLS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Deserialize)]
+struct HordeResponse {
+    id: Option<String>,
+    message: Option<String>,
+}
+
+/// Submits an asynchronous request to `endpoint` (relative to the API root)
+/// and returns the id to poll its status with.
+pub async fn submit_async<T: Serialize + Sync + ?Sized>(
+    reqwest_client: &Client,
+    endpoint: &str,
+    body: &T,
+    retry_observer: &dyn RetryObserver,
+) -> Result<String, HordeError> {
+    let submit_request = retry::send(
+        || {
+            reqwest_client
+                .post(format!("{}/{}", HORDE_API_URL, endpoint))
+                .header("apikey", env::var("HORDE_TOKEN").unwrap())
+                .json(body)
+        },
+        Idempotency::NonIdempotent,
+        retry_observer,
+    )
+    .await;
+
+    match submit_request {
+        Ok(r) => match r.json::<HordeResponse>().await {
+            Ok(j) => match j.id {
+                Some(id) => Ok(id),
+                None => Err(HordeError {
+                    message: format!(
+                        "{:#?}",
+                        j.message
+                            .unwrap_or_else(|| "The Horde did not return a request id".to_string())
+                    ),
+                }),
+            },
+            Err(e) => Err(HordeError {
+                message: format!("{:#?}", e),
+            }),
+        },
+        Err(e) => Err(HordeError {
+            message: format!("{:#?}", e),
+        }),
+    }
+}
+
+/// A model as reported by the Horde status endpoint.
+#[derive(Clone, Deserialize)]
+pub struct HordeModel {
+    pub name: String,
+    pub count: u32,
+}
+
+#[derive(Clone, Copy)]
+pub enum ModelType {
+    Image,
+    Text,
+}
+
+impl ModelType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ModelType::Image => "image",
+            ModelType::Text => "text",
+        }
+    }
+}
+
+const MODEL_CACHE_TTL: Duration = Duration::from_secs(60);
+
+type ModelCache = HashMap<&'static str, (Instant, Vec<HordeModel>)>;
+
+static MODELS: Lazy<Mutex<ModelCache>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Lists models of `model_type` that currently have workers, busiest first.
+/// Results are cached briefly since this backs autocomplete, which has a
+/// tight deadline.
+pub async fn models(
+    reqwest_client: &Client,
+    model_type: ModelType,
+) -> Result<Vec<HordeModel>, HordeError> {
+    if let Some((fetched_at, models)) = MODELS.lock().unwrap().get(model_type.as_str()) {
+        if fetched_at.elapsed() < MODEL_CACHE_TTL {
+            return Ok(models.clone());
+        }
+    }
+
+    let mut models = match reqwest_client
+        .get(format!(
+            "{}/status/models?type={}",
+            HORDE_API_URL,
+            model_type.as_str()
+        ))
+        .send()
+        .await
+    {
+        Ok(r) => match r.json::<Vec<HordeModel>>().await {
+            Ok(m) => m,
+            Err(e) => {
+                return Err(HordeError {
+                    message: format!("{:#?}", e),
+                })
+            }
+        },
+        Err(e) => {
+            return Err(HordeError {
+                message: format!("{:#?}", e),
+            })
+        }
+    };
+
+    models.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    MODELS
+        .lock()
+        .unwrap()
+        .insert(model_type.as_str(), (Instant::now(), models.clone()));
+    Ok(models)
+}
+
+/// Counts the workers serving any of `names`.
+pub async fn worker_count(
+    reqwest_client: &Client,
+    model_type: ModelType,
+    names: &[&str],
+) -> Option<u32> {
+    match models(reqwest_client, model_type).await {
+        Ok(models) => Some(
+            models
+                .iter()
+                .filter(|m| names.contains(&m.name.as_str()))
+                .map(|m| m.count)
+                .sum(),
+        ),
+        Err(e) => {
+            log::warn!("Failed to count Horde workers: {}", e.message);
+            None
+        }
+    }
+}
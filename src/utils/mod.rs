@@ -1,2 +1,5 @@
 pub mod embed;
 pub mod google_ai;
+pub mod horde;
+pub mod preprocess;
+pub mod retry;
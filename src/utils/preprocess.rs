@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use image::{
+    AnimationDecoder, DynamicImage, Frames, ImageDecoder, ImageError, ImageFormat, ImageReader,
+    Rgb, RgbImage,
+};
+
+/// Most frames read from an animated input.
+const MAX_ANIMATION_FRAMES: usize = 500;
+/// Most pixels read across every frame of an animated input.
+const MAX_ANIMATION_PIXELS: u64 = 100_000_000;
+
+#[derive(Clone, Copy, Debug)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    /// Lossless only, the encoder has no quality setting.
+    WebP,
+}
+
+impl OutputFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpeg" | "jpg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::WebP),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum AlphaHandling {
+    /// Keep transparency. Images with an alpha channel are encoded as PNG
+    /// when the output format cannot store one.
+    Keep,
+    /// Composite transparent pixels onto a solid background.
+    Flatten(Rgb<u8>),
+}
+
+/// How an input image is prepared before it is sent to a model.
+#[derive(Clone, Copy, Debug)]
+pub struct ImagePolicy {
+    /// Longest edge in pixels, larger images are scaled down.
+    pub max_edge: u32,
+    pub format: OutputFormat,
+    /// JPEG quality from 1 to 100.
+    pub quality: u8,
+    /// Rotate and flip according to the EXIF orientation tag.
+    pub fix_orientation: bool,
+    pub alpha: AlphaHandling,
+}
+
+/// Frames kept from an animated input.
+pub struct Animation {
+    pub frame_count: usize,
+    /// Indices of the kept frames, in the order `pick` returned them.
+    pub picked: Vec<usize>,
+    pub frames: Vec<DynamicImage>,
+}
+
+#[derive(Debug)]
+pub enum AnimationError {
+    Image(ImageError),
+    /// More than `MAX_ANIMATION_FRAMES` frames, or more than
+    /// `MAX_ANIMATION_PIXELS` across them.
+    TooLarge,
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image(e) => write!(f, "{}", e),
+            Self::TooLarge => write!(
+                f,
+                "The animation is too large. Use one with at most {} frames and {} megapixels across all frames.",
+                MAX_ANIMATION_FRAMES,
+                MAX_ANIMATION_PIXELS / 1_000_000
+            ),
+        }
+    }
+}
+
+impl From<ImageError> for AnimationError {
+    fn from(e: ImageError) -> Self {
+        AnimationError::Image(e)
+    }
+}
+
+pub struct PreparedImage {
+    pub image: DynamicImage,
+    pub data: Vec<u8>,
+}
+
+/// Decodes `bytes` and applies `policy`, returning the processed image and
+/// its encoded form.
+pub fn preprocess(bytes: &[u8], policy: &ImagePolicy) -> Result<PreparedImage, ImageError> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+
+    if policy.fix_orientation {
+        image.apply_orientation(orientation);
+    }
+
+    prepare(image, policy)
+}
+
+/// Applies `policy` to an already decoded image.
+pub fn prepare(mut image: DynamicImage, policy: &ImagePolicy) -> Result<PreparedImage, ImageError> {
+    if image.width() > policy.max_edge || image.height() > policy.max_edge {
+        image = image.thumbnail(policy.max_edge, policy.max_edge);
+    }
+
+    if let AlphaHandling::Flatten(background) = policy.alpha {
+        if image.color().has_alpha() {
+            image = flatten(&image, background);
+        }
+    }
+
+    let format = match policy.format {
+        OutputFormat::Jpeg if image.color().has_alpha() => OutputFormat::Png,
+        format => format,
+    };
+
+    let mut data = Vec::new();
+    match format {
+        OutputFormat::Png => image.write_with_encoder(PngEncoder::new(&mut data))?,
+        OutputFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(
+                &mut data,
+                policy.quality.clamp(1, 100),
+            ))?,
+        // The WebP encoder only accepts 8-bit RGB(A)
+        OutputFormat::WebP if image.color().has_alpha() => image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut data))?,
+        OutputFormat::WebP => image
+            .to_rgb8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut data))?,
+    }
+
+    Ok(PreparedImage { image, data })
+}
+
+/// Decodes the frames `pick` chooses out of an animated GIF or WebP, or
+/// returns `None` for still images, including single frame GIFs. `pick` is
+/// given the frame count, which is at least two.
+///
+/// Frames are decoded one at a time and only the picked ones are kept, so
+/// memory use does not grow with the length of the animation.
+pub fn decode_animation(
+    bytes: &[u8],
+    pick: impl FnOnce(usize) -> Vec<usize>,
+) -> Result<Option<Animation>, AnimationError> {
+    let Some(frames) = animation_frames(bytes)? else {
+        return Ok(None);
+    };
+
+    let mut frame_count = 0;
+    let mut pixels: u64 = 0;
+    for frame in frames {
+        let (width, height) = frame?.buffer().dimensions();
+        frame_count += 1;
+        pixels += width as u64 * height as u64;
+        if frame_count > MAX_ANIMATION_FRAMES || pixels > MAX_ANIMATION_PIXELS {
+            return Err(AnimationError::TooLarge);
+        }
+    }
+    if frame_count < 2 {
+        return Ok(None);
+    }
+
+    let picked = pick(frame_count);
+    let last = picked.iter().copied().max().unwrap_or(0);
+    let mut kept = HashMap::new();
+    let frames = animation_frames(bytes)?.into_iter().flatten();
+    for (index, frame) in frames.enumerate().take(last + 1) {
+        let frame = frame?;
+        if picked.contains(&index) {
+            kept.insert(index, DynamicImage::ImageRgba8(frame.into_buffer()));
+        }
+    }
+
+    Ok(Some(Animation {
+        frame_count,
+        frames: picked.iter().filter_map(|i| kept.get(i).cloned()).collect(),
+        picked,
+    }))
+}
+
+/// The frame iterator of an animated GIF or WebP.
+fn animation_frames(bytes: &[u8]) -> Result<Option<Frames<'_>>, ImageError> {
+    match image::guess_format(bytes)? {
+        ImageFormat::Gif => Ok(Some(GifDecoder::new(Cursor::new(bytes))?.into_frames())),
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            Ok(Some(decoder.into_frames()))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn flatten(image: &DynamicImage, background: Rgb<u8>) -> DynamicImage {
+    let rgba = image.to_rgba8();
+    let mut flattened = RgbImage::from_pixel(rgba.width(), rgba.height(), background);
+
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        let alpha = a as u16;
+        let blend = |fg: u8, bg: u8| ((fg as u16 * alpha + bg as u16 * (255 - alpha)) / 255) as u8;
+        flattened.put_pixel(
+            x,
+            y,
+            Rgb([
+                blend(r, background[0]),
+                blend(g, background[1]),
+                blend(b, background[2]),
+            ]),
+        );
+    }
+
+    DynamicImage::ImageRgb8(flattened)
+}
@@ -0,0 +1,226 @@
+//! Retries for provider requests that fail transiently: 5xx responses,
+//! timeouts and dropped connections.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use twilight_http::client::InteractionClient;
+use twilight_model::channel::message::Embed;
+use twilight_util::builder::embed::EmbedFieldBuilder;
+
+const MAX_ATTEMPTS: u32 = 3;
+const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait between attempts. A `Retry-After` beyond this is not waited
+/// out, the error is returned instead.
+const MAX_DELAY: Duration = Duration::from_secs(15);
+
+/// Whether a request may be sent again after a failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Idempotency {
+    /// Reads and other requests without side effects, like status polls and
+    /// token counts. Retried on any transient error.
+    Idempotent,
+    /// Creates a job or a billed generation on the provider. Only retried when the request cannot
+    /// have been acted on: it never connected, or the server turned it away
+    /// before processing it.
+    NonIdempotent,
+}
+
+/// Details of a retry that is about to happen.
+pub struct RetryNotice {
+    /// The attempt about to be made, starting from 2.
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay: Duration,
+    pub reason: String,
+}
+
+impl RetryNotice {
+    /// A field for the command's pending embed.
+    pub fn field(&self) -> EmbedFieldBuilder {
+        EmbedFieldBuilder::new(
+            "Retrying",
+            format!(
+                "Attempt {} of {} in {}s after {}",
+                self.attempt,
+                self.max_attempts,
+                self.delay.as_secs_f32().ceil(),
+                self.reason
+            ),
+        )
+    }
+}
+
+/// Told about each retry, usually to show it to the user.
+#[async_trait]
+pub trait RetryObserver: Send + Sync {
+    async fn retrying(&self, notice: &RetryNotice);
+}
+
+/// Retries silently, apart from logging.
+#[async_trait]
+impl RetryObserver for () {
+    async fn retrying(&self, _notice: &RetryNotice) {}
+}
+
+/// Shows retries on the original interaction response, rendering the
+/// command's pending embeds with the notice as an extra field.
+pub struct PendingEmbedNotice<'a, F> {
+    pub interaction_client: &'a InteractionClient<'a>,
+    pub interaction_token: &'a str,
+    pub render: F,
+}
+
+#[async_trait]
+impl<F> RetryObserver for PendingEmbedNotice<'_, F>
+where
+    F: Fn(EmbedFieldBuilder) -> Vec<Embed> + Send + Sync,
+{
+    async fn retrying(&self, notice: &RetryNotice) {
+        self.interaction_client
+            .update_response(self.interaction_token)
+            .embeds(Some(&(self.render)(notice.field())))
+            .await
+            .ok();
+    }
+}
+
+/// Tracks the attempts of one request and decides whether a failure is
+/// worth another one.
+pub struct Retry<'a> {
+    idempotency: Idempotency,
+    observer: &'a dyn RetryObserver,
+    attempt: u32,
+}
+
+impl<'a> Retry<'a> {
+    pub fn new(idempotency: Idempotency, observer: &'a dyn RetryObserver) -> Self {
+        Retry {
+            idempotency,
+            observer,
+            attempt: 1,
+        }
+    }
+
+    /// Called after a non-2xx response. Waits and returns `true` if the
+    /// request should be sent again.
+    pub async fn after_status(&mut self, status_code: StatusCode, headers: &HeaderMap) -> bool {
+        let retry_after = retry_after(headers);
+        let retryable = match status_code {
+            // Quota errors without a hint are not going to clear up in seconds
+            StatusCode::TOO_MANY_REQUESTS => retry_after.is_some(),
+            StatusCode::SERVICE_UNAVAILABLE => true,
+            StatusCode::REQUEST_TIMEOUT
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::GATEWAY_TIMEOUT => self.idempotency == Idempotency::Idempotent,
+            _ => false,
+        };
+        if !retryable {
+            return false;
+        }
+        self.wait(retry_after, format!("HTTP {}", status_code.as_u16()))
+            .await
+    }
+
+    /// Called after a request failed without a response. Waits and returns
+    /// `true` if the request should be sent again.
+    pub async fn after_error(&mut self, error: &reqwest::Error) -> bool {
+        let retryable = match self.idempotency {
+            Idempotency::Idempotent => {
+                error.is_timeout() || error.is_connect() || error.is_request()
+            }
+            Idempotency::NonIdempotent => error.is_connect(),
+        };
+        if !retryable {
+            return false;
+        }
+        let reason = match error.is_timeout() {
+            true => "a timeout".to_string(),
+            false => "a connection error".to_string(),
+        };
+        self.wait(None, reason).await
+    }
+
+    async fn wait(&mut self, retry_after: Option<Duration>, reason: String) -> bool {
+        if self.attempt >= MAX_ATTEMPTS {
+            return false;
+        }
+        let delay = match retry_after {
+            Some(retry_after) if retry_after > MAX_DELAY => return false,
+            Some(retry_after) => retry_after,
+            None => backoff(self.attempt),
+        };
+        self.attempt += 1;
+
+        let notice = RetryNotice {
+            attempt: self.attempt,
+            max_attempts: MAX_ATTEMPTS,
+            delay,
+            reason,
+        };
+        log::info!(
+            "Retrying request after {} (attempt {} of {}) in {:?}",
+            notice.reason,
+            notice.attempt,
+            notice.max_attempts,
+            delay
+        );
+        self.observer.retrying(&notice).await;
+        tokio::time::sleep(delay).await;
+        true
+    }
+}
+
+/// Exponential backoff with jitter, so requests that failed together do
+/// not all come back at once.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_DELAY);
+    delay.mul_f64(rand::rng().random_range(0.5..=1.0))
+}
+
+/// `Retry-After` in seconds. HTTP dates are not used by any of our
+/// providers and are ignored.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Sends the request built by `request`, retrying transient failures. The
+/// last response is returned as is, whatever its status.
+pub async fn send(
+    request: impl Fn() -> RequestBuilder,
+    idempotency: Idempotency,
+    observer: &dyn RetryObserver,
+) -> Result<Response, reqwest::Error> {
+    let mut retry = Retry::new(idempotency, observer);
+    loop {
+        match request().send().await {
+            Ok(response) if !response.status().is_success() => {
+                if !retry
+                    .after_status(response.status(), response.headers())
+                    .await
+                {
+                    return Ok(response);
+                }
+            }
+            Ok(response) => return Ok(response),
+            Err(e) => {
+                if !retry.after_error(&e).await {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}